@@ -0,0 +1,36 @@
+use anyhow::Error;
+use clap::Args;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use guten_rs::catalog::read_catalog;
+
+use crate::crawl::create_parent;
+
+/// Turn the RDF catalog into a JSON map of ebook id to its metadata
+#[derive(Args, Debug, Clone)]
+pub struct CatalogArgs {
+    /// rdf-files.tar.bz2 from gutenberg.org/cache/epub/feeds/, or the .tar
+    #[arg(short, long, value_name = "ARCHIVE",
+    value_hint=clap::ValueHint::FilePath)]
+    pub input_file: PathBuf,
+
+    #[arg(short, long, value_name = "OUTPUT",
+    value_hint=clap::ValueHint::FilePath, default_value = ".cache/catalog.json")]
+    pub output_file: PathBuf,
+}
+
+pub fn run(args: &CatalogArgs) -> Result<(), Error> {
+    let catalog = read_catalog(&args.input_file)?;
+    for (path, error) in &catalog.errors {
+        eprintln!("{}: {}", path, error);
+    }
+    println!("{} ebooks in the catalog", catalog.entries.len());
+
+    create_parent(&args.output_file)?;
+    let mut writer = BufWriter::new(File::create(&args.output_file)?);
+    serde_json::to_writer(&mut writer, &catalog.entries)?;
+    writer.flush()?;
+    Ok(())
+}
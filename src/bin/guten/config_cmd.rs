@@ -0,0 +1,43 @@
+use anyhow::Error;
+use clap::{Args, Subcommand};
+use std::path::{Path, PathBuf};
+
+use guten_rs::config;
+
+/// Write or check the config file
+#[derive(Args, Debug, Clone)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Write a fully commented config with the default values
+    Init {
+        #[arg(short, long, value_name = "FILE", default_value = "./config.toml")]
+        output: PathBuf,
+
+        /// Replace an existing file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Load the config the other commands would use and report every problem
+    Check,
+}
+
+pub fn run(args: &ConfigArgs, config_path: Option<&Path>) -> Result<(), Error> {
+    match &args.command {
+        ConfigCommand::Init { output, force } => {
+            config::write_default_config(output, *force)?;
+            println!("Wrote {}", output.display());
+            Ok(())
+        }
+        ConfigCommand::Check => {
+            // Loading already runs the validation
+            let config = config::load_config(config_path)?;
+            println!("Config is valid, crawling {}", config.url);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::Error;
+use clap::Args;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use guten_rs::config::Config;
+use guten_rs::crawler::{CrawlState, Crawler};
+use guten_rs::ebook_ids;
+use guten_rs::manifest::{self, ManifestDiff};
+use guten_rs::shutdown::{self, Shutdown};
+
+/// Walk the mirror and write the manifest of files to download
+#[derive(Args, Debug, Clone)]
+pub struct CrawlArgs {
+    /// Continue from the last checkpoint in the state file. The state does
+    /// not record which ebooks it was limited to, so --ids cannot be resumed
+    #[arg(long, conflicts_with_all = ["ids", "id_file"])]
+    pub resume: bool,
+
+    /// Compare against the previous manifest and write the changes to the diff file
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Only crawl these ebooks instead of the whitelist, e.g. "1000-2000,42".
+    /// Their entries replace those already in the manifest
+    #[arg(long, value_name = "IDS", conflicts_with = "id_file")]
+    pub ids: Option<String>,
+
+    /// Like --ids, read from a file. Accepts the JSON written by `index`
+    #[arg(long, value_name = "FILE")]
+    pub id_file: Option<PathBuf>,
+
+    /// Where the manifest is written
+    #[arg(
+        short,
+        long,
+        value_name = "MANIFEST",
+        default_value = ".cache/manifest.jsonl"
+    )]
+    pub output: PathBuf,
+
+    /// Checkpoint for --resume
+    #[arg(long, value_name = "FILE", default_value = ".cache/crawl_state.json")]
+    pub state: PathBuf,
+
+    /// Changes found by --incremental
+    #[arg(long, value_name = "FILE", default_value = ".cache/diff.json")]
+    pub diff: PathBuf,
+}
+
+pub async fn run(args: &CrawlArgs, config: &Config, shutdown: &Shutdown) -> Result<(), Error> {
+    let pb = ProgressBar::no_length();
+
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {per_sec:>5} {msg}",
+        )
+        .unwrap()
+        .progress_chars("##-"),
+    );
+    for path in [&args.output, &args.state, &args.diff] {
+        create_parent(path)?;
+    }
+
+    let ids = match (&args.ids, &args.id_file) {
+        (Some(spec), _) => Some(ebook_ids::parse_id_ranges(spec)?),
+        (None, Some(path)) => Some(ebook_ids::read_id_file(path)?),
+        (None, None) => None,
+    };
+    let mut crawler = Crawler::new(config)?
+        .with_progress(pb)
+        .with_shutdown(shutdown.clone());
+    // An id crawl is short and cannot be resumed, it must not replace or
+    // remove the checkpoint of a full crawl
+    if ids.is_none() {
+        crawler = crawler.with_checkpoint(args.state.clone());
+    }
+    let state = if args.resume && args.state.exists() {
+        let state = CrawlState::load(&args.state)?;
+        println!(
+            "Resuming crawl: {} queued, {} visited, {} files",
+            state.queue.len(),
+            state.visited.len(),
+            state.files.len()
+        );
+        state
+    } else {
+        if args.resume {
+            println!(
+                "No crawl state found at {}, starting fresh",
+                args.state.display()
+            );
+        }
+        match &ids {
+            Some(ids) => {
+                println!("Crawling {} ebooks", ids.len());
+                crawler.state_for_ids(ids)
+            }
+            None => crawler.initial_state(),
+        }
+    };
+    let summary = match crawler.run(state).await {
+        Ok(summary) => summary,
+        Err(e) if shutdown::is_interrupted(&e) => {
+            if ids.is_some() {
+                println!("Crawl interrupted, run the same command again to start over");
+            } else {
+                println!(
+                    "Crawl state saved to {}, run again with --resume to continue",
+                    args.state.display()
+                );
+            }
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut files = summary.files;
+    println!(
+        "Found {} files, skipped {} excluded directories",
+        files.len(),
+        summary.excluded.len()
+    );
+    let previous = if args.output.exists() {
+        Some(manifest::read_manifest(&args.output)?)
+    } else {
+        None
+    };
+    if !summary.failed.is_empty() {
+        let retry = if ids.is_some() {
+            "run the same command again"
+        } else {
+            "run again with --resume"
+        };
+        eprintln!(
+            "{} directories could not be listed, {} to retry them",
+            summary.failed.len(),
+            retry
+        );
+        // A listing that failed says nothing about its files, keep what the
+        // last crawl found there instead of reporting them as removed
+        if let Some(previous) = &previous {
+            let kept = manifest::under_directories(previous, &summary.failed);
+            println!(
+                "Kept {} files from the previous manifest under them",
+                kept.len()
+            );
+            files.extend(kept);
+        }
+    }
+    if args.incremental {
+        match &previous {
+            Some(previous) => {
+                // Only the requested ebooks were crawled, the rest did not go away
+                let changes: ManifestDiff = match &ids {
+                    Some(ids) => manifest::diff(&manifest::only_ebooks(previous, ids), &files),
+                    None => manifest::diff(previous, &files),
+                };
+                println!(
+                    "Since last crawl: {} added, {} removed, {} changed",
+                    changes.added.len(),
+                    changes.removed.len(),
+                    changes.changed.len()
+                );
+                changes.save(&args.diff)?;
+            }
+            None => println!(
+                "No previous manifest at {}, nothing to compare",
+                args.output.display()
+            ),
+        }
+    }
+    let files = match (previous, &ids) {
+        (Some(previous), Some(ids)) => manifest::merge_ebooks(previous, &files, ids),
+        _ => files,
+    };
+    manifest::write_manifest(&args.output, &files)?;
+
+    Ok(())
+}
+
+/// Makes sure the folder of an output file exists, e.g. .cache
+pub fn create_parent(path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        if parent != Path::new("") {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    Ok(())
+}
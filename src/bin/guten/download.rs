@@ -0,0 +1,126 @@
+use anyhow::Error;
+use clap::Args;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use guten_rs::config::Config;
+use guten_rs::downloader;
+use guten_rs::manifest::{self, ManifestDiff, ManifestEntry};
+use guten_rs::report;
+use guten_rs::shutdown::{Interrupted, Shutdown};
+
+use crate::crawl::create_parent;
+
+/// Fetch the files listed in the manifest
+#[derive(Args, Debug, Clone)]
+pub struct DownloadArgs {
+    /// Manifest written by `crawl`
+    #[arg(
+        short,
+        long,
+        value_name = "MANIFEST",
+        default_value = ".cache/manifest.jsonl"
+    )]
+    pub manifest: PathBuf,
+
+    /// Only fetch the files added or changed since the last crawl
+    #[arg(long, conflicts_with = "retry_failed")]
+    pub delta: bool,
+
+    /// Changes written by `crawl --incremental`, read with --delta
+    #[arg(long, value_name = "FILE", default_value = ".cache/diff.json")]
+    pub diff: PathBuf,
+
+    /// Where to write the per-file outcome of this run (JSONL)
+    #[arg(
+        long,
+        value_name = "REPORT",
+        default_value = ".cache/download_report.jsonl"
+    )]
+    pub report: PathBuf,
+
+    /// Only fetch the files that failed in the last report
+    #[arg(long)]
+    pub retry_failed: bool,
+
+    /// Download folder, defaults to `download_path` from the config
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+}
+
+impl DownloadArgs {
+    pub fn download_dir(&self, config: &Config) -> PathBuf {
+        match (&self.output, &config.download_path) {
+            (Some(dir), _) => dir.clone(),
+            (None, Some(dir)) => PathBuf::from(dir),
+            (None, None) => PathBuf::from("./download"),
+        }
+    }
+}
+
+/// Returns how many files failed, they are listed in the report
+pub async fn run(
+    args: &DownloadArgs,
+    config: &Config,
+    shutdown: &Shutdown,
+) -> Result<usize, Error> {
+    let download_path = args.download_dir(config);
+    let entries = if args.retry_failed {
+        let failed = report::failed_urls(&report::read_report(&args.report)?);
+        println!(
+            "Retrying {} failed downloads from {}",
+            failed.len(),
+            args.report.display()
+        );
+        // Take size and mtime from the manifest when the url is still listed
+        let mut by_url: HashMap<String, ManifestEntry> = if args.manifest.exists() {
+            manifest::read_manifest(&args.manifest)?
+                .into_iter()
+                .map(|entry| (entry.url.clone(), entry))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        failed
+            .into_iter()
+            .map(|url| {
+                by_url
+                    .remove(&url)
+                    .unwrap_or_else(|| ManifestEntry::new(&config.url, &url, None, None))
+            })
+            .collect()
+    } else if args.delta {
+        let changes = ManifestDiff::load(&args.diff)?;
+        if !changes.removed.is_empty() {
+            println!(
+                "{} files were removed from the mirror, local copies are kept",
+                changes.removed.len()
+            );
+        }
+        changes.to_download()
+    } else {
+        manifest::read_manifest(&args.manifest)?
+    };
+    let records =
+        downloader::download(entries, &download_path.to_string_lossy(), config, shutdown).await?;
+
+    create_parent(&args.report)?;
+    report::write_report(&args.report, &records)?;
+    if shutdown.is_triggered() {
+        println!(
+            "Download interrupted, {} finished files are in {}, run again to resume the rest",
+            records.len(),
+            args.report.display()
+        );
+        return Err(Interrupted.into());
+    }
+    let failed = report::failed_urls(&records).len();
+    if failed > 0 {
+        eprintln!(
+            "{} downloads failed, see {} and run again with --retry-failed",
+            failed,
+            args.report.display()
+        );
+    }
+    Ok(failed)
+}
@@ -0,0 +1,104 @@
+//This helper indexes the GUTENBURG INDEX into structured file
+
+use anyhow::anyhow;
+use clap::Args;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use guten_rs::index::{parse_index, MergedIndex};
+
+use crate::crawl::create_parent;
+
+/// Turn GUTINDEX files into a JSON map of ebook id to title, author and tags
+#[derive(Args, Debug, Clone)]
+pub struct IndexArgs {
+    /// Index files or globs, e.g. "GUTINDEX.*". When files disagree on an
+    /// ebook the one given later wins
+    #[arg(short, long, value_name = "INDEX", num_args = 1.., required = true,
+    value_hint=clap::ValueHint::FilePath)]
+    pub input_file: Vec<String>,
+
+    #[arg(short, long, value_name = "OUTPUT",
+    value_hint=clap::ValueHint::FilePath, default_value = ".cache/index.json")]
+    pub output_file: PathBuf,
+
+    /// Where to list the ebooks whose title differs between files
+    #[arg(
+        long,
+        value_name = "FILE",
+        default_value = ".cache/index_conflicts.json"
+    )]
+    pub conflicts: PathBuf,
+}
+
+pub fn run(args: &IndexArgs) -> Result<(), anyhow::Error> {
+    let mut merged = MergedIndex::default();
+    for input_path in input_files(&args.input_file)? {
+        let string = fs::read_to_string(&input_path)
+            .map_err(|e| anyhow!("{}: {}", input_path.display(), e))?;
+        let parsed = parse_index(&string);
+        for line in &parsed.unparsed {
+            eprintln!(
+                "{}:{}: not an index entry: {}",
+                input_path.display(),
+                line.line,
+                line.text.trim()
+            );
+        }
+        for duplicate in &parsed.duplicates {
+            eprintln!(
+                "{}:{}: ebook {} is listed again, keeping the first listing",
+                input_path.display(),
+                duplicate.line,
+                duplicate.id
+            );
+        }
+        let entries = parsed.into_entries();
+        println!("{}: {} entries", input_path.display(), entries.len());
+        merged.add(&input_path.display().to_string(), entries);
+    }
+    println!("{} ebooks in total", merged.entries.len());
+
+    create_parent(&args.output_file)?;
+    let output_file = File::create(&args.output_file)?;
+    let toml_string = serde_json::to_string(&merged.entries)?;
+    let mut writer = std::io::BufWriter::new(output_file);
+    writer.write_all(toml_string.as_bytes())?;
+
+    if !merged.conflicts.is_empty() {
+        create_parent(&args.conflicts)?;
+        fs::write(
+            &args.conflicts,
+            serde_json::to_string_pretty(&merged.conflicts)?,
+        )?;
+        eprintln!(
+            "{} ebooks have different titles across the files, see {}",
+            merged.conflicts.len(),
+            args.conflicts.display()
+        );
+    }
+    Ok(())
+}
+
+// Existing files are taken as is, anything else is expanded as a glob
+fn input_files(inputs: &[String]) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = PathBuf::from(input);
+        if path.is_file() {
+            files.push(path);
+            continue;
+        }
+        let mut matched: Vec<PathBuf> = glob::glob(input)?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect();
+        if matched.is_empty() {
+            return Err(anyhow!("{}: no such file", input));
+        }
+        matched.sort();
+        files.append(&mut matched);
+    }
+    Ok(files)
+}
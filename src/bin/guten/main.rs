@@ -0,0 +1,164 @@
+use anyhow::{anyhow, Error};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use guten_rs::config::{self, Config};
+use guten_rs::shutdown::{self, Shutdown, INTERRUPTED_EXIT_CODE};
+
+mod catalog;
+mod config_cmd;
+mod crawl;
+mod download;
+mod index;
+mod process;
+
+use catalog::CatalogArgs;
+use config_cmd::ConfigArgs;
+use crawl::CrawlArgs;
+use download::DownloadArgs;
+use index::IndexArgs;
+use process::ProcessArgs;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Config file. Without it $GUTEN_CONFIG, ./config.toml and the XDG
+    /// config dirs (~/.config/guten/config.toml, /etc/xdg/guten/config.toml)
+    /// are tried in turn. GUTEN_* variables override single keys
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Crawl(CrawlArgs),
+    Download(DownloadArgs),
+    Process(ProcessArgs),
+    Index(IndexArgs),
+    Catalog(CatalogArgs),
+    Pipeline(PipelineArgs),
+    Config(ConfigArgs),
+}
+
+/// Crawl, download and process in one go
+#[derive(Args, Debug, Clone)]
+struct PipelineArgs {
+    /// Continue an interrupted crawl
+    #[arg(long, conflicts_with_all = ["ids", "id_file"])]
+    resume: bool,
+
+    /// Only download what changed since the last crawl
+    #[arg(long)]
+    incremental: bool,
+
+    /// Only these ebooks instead of the whitelist, e.g. "1000-2000,42"
+    #[arg(long, value_name = "IDS", conflicts_with = "id_file")]
+    ids: Option<String>,
+
+    /// Like --ids, read from a file. Accepts the JSON written by `index`
+    #[arg(long, value_name = "FILE")]
+    id_file: Option<PathBuf>,
+
+    /// Folder for the manifest, crawl state, diff and download report
+    #[arg(long, value_name = "DIR", default_value = ".cache")]
+    cache_dir: PathBuf,
+
+    /// Download folder, defaults to `download_path` from the config
+    #[arg(long, value_name = "DIR")]
+    download_dir: Option<PathBuf>,
+
+    /// Where the cleaned files are written
+    #[arg(short, long, value_name = "DIR", default_value = "./cleaned")]
+    output: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let cli = Cli::parse();
+    // Retries and skipped files would scroll the download progress away
+    let default_filter = match cli.command {
+        Command::Crawl(_) => "info",
+        _ => "warn",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter))
+        .init();
+
+    let shutdown = Shutdown::new();
+    shutdown.install_ctrlc()?;
+    let config_path = cli.config.as_deref();
+    let result = match &cli.command {
+        Command::Crawl(args) => {
+            crawl::run(args, &config::load_config(config_path)?, &shutdown).await
+        }
+        Command::Download(args) => {
+            let config = config::load_config(config_path)?;
+            let failed = download::run(args, &config, &shutdown).await?;
+            if failed > 0 {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Process(args) => process::run(args, &config::load_config(config_path)?, &shutdown),
+        Command::Index(args) => index::run(args),
+        Command::Catalog(args) => catalog::run(args),
+        Command::Pipeline(args) => {
+            pipeline(args, &config::load_config(config_path)?, &shutdown).await
+        }
+        Command::Config(args) => config_cmd::run(args, config_path),
+    };
+    match result {
+        Err(e) if shutdown::is_interrupted(&e) => std::process::exit(INTERRUPTED_EXIT_CODE),
+        result => result,
+    }
+}
+
+async fn pipeline(args: &PipelineArgs, config: &Config, shutdown: &Shutdown) -> Result<(), Error> {
+    let crawl = CrawlArgs {
+        resume: args.resume,
+        incremental: args.incremental,
+        ids: args.ids.clone(),
+        id_file: args.id_file.clone(),
+        output: args.cache_dir.join("manifest.jsonl"),
+        state: args.cache_dir.join("crawl_state.json"),
+        diff: args.cache_dir.join("diff.json"),
+    };
+    // A first incremental crawl has nothing to compare, fetch everything
+    let delta = args.incremental && crawl.output.exists();
+    println!("== Crawling {}", config.url);
+    crawl::run(&crawl, config, shutdown).await?;
+
+    let download = DownloadArgs {
+        manifest: crawl.output.clone(),
+        delta,
+        diff: crawl.diff.clone(),
+        report: args.cache_dir.join("download_report.jsonl"),
+        retry_failed: false,
+        output: args.download_dir.clone(),
+    };
+    println!(
+        "== Downloading to {}",
+        download.download_dir(config).display()
+    );
+    let failed = download::run(&download, config, shutdown).await?;
+
+    // Whatever did arrive is still worth processing
+    let process = ProcessArgs {
+        manifest: Some(crawl.output.clone()),
+        input: Some(download.download_dir(config)),
+        output: args.output.clone(),
+    };
+    println!("== Processing into {}", process.output.display());
+    process::run(&process, config, shutdown)?;
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} downloads failed, run `guten download --retry-failed --report {}`",
+            failed,
+            download.report.display()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use anyhow::Error;
+use clap::Args;
+use std::path::{Path, PathBuf};
+
+// There is not much IO, so rayon can be utilized here
+use guten_rs::config::Config;
+use guten_rs::manifest::{self, FileKind};
+use guten_rs::postprocess;
+use guten_rs::shutdown::{Interrupted, Shutdown};
+use rayon::prelude::*;
+
+use glob::glob;
+
+/// Unzip the downloads and write cleaned text files
+#[derive(Args, Debug, Clone)]
+pub struct ProcessArgs {
+    /// Only process the files in this manifest instead of the whole download folder
+    #[arg(short, long, value_name = "MANIFEST")]
+    pub manifest: Option<PathBuf>,
+
+    /// Download folder, defaults to `download_path` from the config
+    #[arg(short, long, value_name = "DIR")]
+    pub input: Option<PathBuf>,
+
+    /// Where the cleaned files are written
+    #[arg(short, long, value_name = "DIR", default_value = "./cleaned")]
+    pub output: PathBuf,
+}
+
+pub fn run(args: &ProcessArgs, config: &Config, shutdown: &Shutdown) -> Result<(), Error> {
+    // Work already started is allowed to finish, nothing new is picked up
+    let download_dir = match (&args.input, &config.download_path) {
+        (Some(dir), _) => dir.to_string_lossy().into_owned(),
+        (None, Some(dir)) => dir.clone(),
+        (None, None) => "./download".to_string(),
+    };
+    let download_dir = download_dir.as_str();
+    // Stage 1: Unzip all the files into their own folders
+    let output_path = args.output.as_path();
+
+    let all_files: Vec<PathBuf> = match &args.manifest {
+        Some(manifest_path) => {
+            let entries = manifest::read_manifest(manifest_path)?;
+            entries
+                .par_iter()
+                .flat_map(|entry| {
+                    if shutdown.is_triggered() {
+                        return Vec::new();
+                    }
+                    let local = Path::new(download_dir).join(&entry.path);
+                    if entry.kind != FileKind::Zip {
+                        return vec![local];
+                    }
+                    match postprocess::unarchive::unzip(&local.display().to_string(), false) {
+                        Ok(extracted) => extracted,
+                        Err(e) => {
+                            println!("{}: {:?}", local.display(), e);
+                            Vec::new()
+                        }
+                    }
+                })
+                .collect()
+        }
+        None => {
+            let pattern = format!("{}/**/*.zip", download_dir);
+            let zip_files: Vec<_> = glob(&pattern)?.collect();
+
+            zip_files.par_iter().for_each(|entry| match entry {
+                Ok(_) if shutdown.is_triggered() => {}
+                Ok(path) => {
+                    let path_str = path.display().to_string();
+                    let _ = postprocess::unarchive::unzip(&path_str, false);
+                }
+                Err(e) => println!("{:?}", e),
+            });
+            // Now match all
+            let pattern = format!("{}/**/*", download_dir);
+            // Only txt supported for now
+            glob(&pattern)?.filter_map(Result::ok).collect()
+        }
+    };
+
+    println!("Found {} files to process", all_files.len());
+    // Stage 2: Start the data processing
+    all_files
+        .par_iter()
+        .filter(|source_file| source_file.extension().is_some())
+        .filter(|_| !shutdown.is_triggered())
+        .for_each(|source_file| {
+            // Now we only process files that have extensions
+            let extension = source_file.extension().unwrap();
+
+            if extension == "txt" {
+                postprocess::clean::clean_txt(source_file, download_dir, output_path).unwrap();
+            }
+            // Input other file types here
+            // else if extension == "pdf" {
+        });
+
+    if shutdown.is_triggered() {
+        println!(
+            "Processing interrupted, finished files are kept in {}",
+            output_path.display()
+        );
+        return Err(Interrupted.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,451 @@
+use anyhow::{anyhow, Error};
+use bzip2::read::BzDecoder;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// A person credited on an ebook
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Creator {
+    /// As the catalog writes it, e.g. "Austen, Jane"
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub birth_year: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub death_year: Option<i32>,
+    /// MARC relator code, "aut" for the author, "ill" for an illustrator,
+    /// "trl" for a translator...
+    pub role: String,
+}
+
+/// One of the files an ebook is offered as
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CatalogFormat {
+    pub url: String,
+    /// e.g. "text/plain; charset=utf-8", zips also list "application/zip"
+    pub media_types: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// The metadata of one ebook, read from its pgNNNN.rdf
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CatalogEntry {
+    pub id: u32,
+    pub title: String,
+    pub creators: Vec<Creator>,
+    /// Library of Congress subject headings
+    pub subjects: Vec<String>,
+    /// Library of Congress classification, e.g. "PR"
+    pub lcc: Vec<String>,
+    /// RFC 4646 codes, e.g. "en"
+    pub languages: Vec<String>,
+    pub bookshelves: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rights: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued: Option<String>,
+    /// Downloads from gutenberg.org in the last 30 days
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloads: Option<u64>,
+    pub formats: Vec<CatalogFormat>,
+}
+
+impl CatalogEntry {
+    pub fn authors(&self) -> impl Iterator<Item = &Creator> {
+        self.creators
+            .iter()
+            .filter(|creator| creator.role == AUTHOR_ROLE)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Catalog {
+    pub entries: BTreeMap<u32, CatalogEntry>,
+    /// RDF files in the archive that could not be read, with the reason
+    pub errors: Vec<(String, String)>,
+}
+
+const AUTHOR_ROLE: &str = "aut";
+
+/// Reads every pgNNNN.rdf in `path`, the `rdf-files.tar.bz2` from
+/// gutenberg.org/cache/epub/feeds/ or an unpacked `.tar`. The archive is
+/// streamed, only the parsed entries are kept in memory.
+pub fn read_catalog(path: &Path) -> Result<Catalog, Error> {
+    let file = BufReader::new(File::open(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?);
+    let is_bz2 = path.extension().is_some_and(|extension| extension == "bz2");
+    let reader: Box<dyn Read> = if is_bz2 {
+        Box::new(BzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut archive = tar::Archive::new(reader);
+    let mut catalog = Catalog::default();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        // cache/epub/1342/pg1342.rdf
+        let Some(file_id) = rdf_file_id(&name) else {
+            continue;
+        };
+        let mut xml = String::new();
+        if let Err(e) = entry.read_to_string(&mut xml) {
+            catalog.errors.push((name, e.to_string()));
+            continue;
+        }
+        match parse_rdf(&xml) {
+            Ok(mut parsed) => {
+                if parsed.id == 0 {
+                    parsed.id = file_id;
+                }
+                catalog.entries.insert(parsed.id, parsed);
+            }
+            Err(e) => catalog.errors.push((name, e.to_string())),
+        }
+    }
+    Ok(catalog)
+}
+
+fn rdf_file_id(path: &str) -> Option<u32> {
+    let name = path.rsplit('/').next()?;
+    name.strip_prefix("pg")?.strip_suffix(".rdf")?.parse().ok()
+}
+
+/// Parses the RDF/XML of one ebook. Elements are matched by the prefixes
+/// the catalog uses (dcterms, pgterms, marcrel, rdf, dcam).
+pub fn parse_rdf(xml: &str) -> Result<CatalogEntry, Error> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut entry = CatalogEntry::default();
+    let mut stack: Vec<String> = Vec::new();
+    let mut text = String::new();
+    // Agents can be written once and referenced by rdf:about elsewhere
+    let mut agents: HashMap<String, Creator> = HashMap::new();
+    let mut credits: Vec<(String, String)> = Vec::new();
+    let mut agent: Option<(String, Creator)> = None;
+    let mut format: Option<CatalogFormat> = None;
+    let mut value: Option<String> = None;
+    let mut scheme: Option<String> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                let name = element_name(&start);
+                text.clear();
+                match name.as_str() {
+                    "pgterms:ebook" => {
+                        if let Some(about) = attribute(&start, "rdf:about")? {
+                            entry.id = about
+                                .rsplit('/')
+                                .next()
+                                .and_then(|id| id.parse().ok())
+                                .unwrap_or(0);
+                        }
+                    }
+                    "pgterms:agent" => {
+                        let about = attribute(&start, "rdf:about")?.unwrap_or_default();
+                        let role = stack.last().map(|parent| role_of(parent));
+                        agent = Some((
+                            about,
+                            Creator {
+                                role: role.unwrap_or(AUTHOR_ROLE).to_string(),
+                                ..Default::default()
+                            },
+                        ));
+                    }
+                    "pgterms:file" => {
+                        format = Some(CatalogFormat {
+                            url: attribute(&start, "rdf:about")?.unwrap_or_default(),
+                            ..Default::default()
+                        });
+                    }
+                    "rdf:Description" => {
+                        value = None;
+                        scheme = None;
+                    }
+                    _ => {}
+                }
+                stack.push(name);
+            }
+            Event::Empty(empty) => {
+                let name = element_name(&empty);
+                let resource = attribute(&empty, "rdf:resource")?;
+                match (name.as_str(), resource) {
+                    ("dcam:memberOf", resource) => scheme = resource,
+                    // <dcterms:creator rdf:resource="2009/agents/68"/>
+                    (name, Some(resource)) if is_credit(name) => {
+                        credits.push((resource, role_of(name).to_string()));
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(content) => text.push_str(&content.unescape()?),
+            Event::CData(content) => text.push_str(&String::from_utf8_lossy(&content)),
+            Event::End(_) => {
+                let Some(name) = stack.pop() else {
+                    return Err(anyhow!("Unbalanced closing tag"));
+                };
+                let parent = stack.last().map(String::as_str).unwrap_or_default();
+                let content = text.trim().to_string();
+                match (parent, name.as_str()) {
+                    ("pgterms:ebook", "dcterms:title") => entry.title = content,
+                    ("pgterms:ebook", "dcterms:rights") => entry.rights = Some(content),
+                    ("pgterms:ebook", "dcterms:issued") => entry.issued = Some(content),
+                    ("pgterms:ebook", "pgterms:downloads") => {
+                        entry.downloads = content.parse().ok()
+                    }
+                    (_, "pgterms:name") => {
+                        if let Some((_, creator)) = &mut agent {
+                            creator.name = content;
+                        }
+                    }
+                    (_, "pgterms:birthdate") => {
+                        if let Some((_, creator)) = &mut agent {
+                            creator.birth_year = content.parse().ok();
+                        }
+                    }
+                    (_, "pgterms:deathdate") => {
+                        if let Some((_, creator)) = &mut agent {
+                            creator.death_year = content.parse().ok();
+                        }
+                    }
+                    (_, "pgterms:agent") => {
+                        if let Some((about, creator)) = agent.take() {
+                            // Only credits directly on the ebook count, an agent
+                            // described on its own is kept for the references
+                            if is_credit(parent) {
+                                entry.creators.push(creator.clone());
+                            }
+                            agents.insert(about, creator);
+                        }
+                    }
+                    ("pgterms:file", "dcterms:extent") => {
+                        if let Some(format) = &mut format {
+                            format.size = content.parse().ok();
+                        }
+                    }
+                    (_, "pgterms:file") => entry.formats.extend(format.take()),
+                    (_, "rdf:value") => value = Some(content),
+                    (container, "rdf:Description") => {
+                        let Some(value) = value.take() else {
+                            text.clear();
+                            continue;
+                        };
+                        let scheme = scheme.take().unwrap_or_default();
+                        match container {
+                            "dcterms:language" => entry.languages.push(value),
+                            "dcterms:subject" if scheme.ends_with("LCC") => entry.lcc.push(value),
+                            "dcterms:subject" => entry.subjects.push(value),
+                            "pgterms:bookshelf" => entry.bookshelves.push(value),
+                            "dcterms:format" => {
+                                if let Some(format) = &mut format {
+                                    format.media_types.push(value);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    for (about, role) in credits {
+        if let Some(creator) = agents.get(&about) {
+            entry.creators.push(Creator {
+                role,
+                ..creator.clone()
+            });
+        }
+    }
+    if entry.id == 0 && entry.title.is_empty() {
+        return Err(anyhow!("No pgterms:ebook found"));
+    }
+    Ok(entry)
+}
+
+fn element_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.name().as_ref()).into_owned()
+}
+
+fn attribute(element: &BytesStart, key: &str) -> Result<Option<String>, Error> {
+    match element.try_get_attribute(key)? {
+        Some(attribute) => Ok(Some(attribute.unescape_value()?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+// dcterms:creator is the author, contributors are marcrel:ill, marcrel:trl...
+fn is_credit(element: &str) -> bool {
+    element == "dcterms:creator" || element.starts_with("marcrel:")
+}
+
+fn role_of(element: &str) -> &str {
+    element.strip_prefix("marcrel:").unwrap_or(AUTHOR_ROLE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bzip2::write::BzEncoder;
+
+    // Trimmed from pg1342.rdf, with the illustrator written as a reference
+    const PRIDE_AND_PREJUDICE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<rdf:RDF xml:base="http://www.gutenberg.org/"
+  xmlns:dcterms="http://purl.org/dc/terms/"
+  xmlns:pgterms="http://www.gutenberg.org/2009/pgterms/"
+  xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+  xmlns:marcrel="http://id.loc.gov/vocabulary/relators/"
+  xmlns:dcam="http://purl.org/dc/dcam/">
+  <pgterms:ebook rdf:about="ebooks/1342">
+    <dcterms:publisher>Project Gutenberg</dcterms:publisher>
+    <dcterms:license rdf:resource="license"/>
+    <dcterms:issued rdf:datatype="http://www.w3.org/2001/XMLSchema#date">1998-06-01</dcterms:issued>
+    <dcterms:rights>Public domain in the USA.</dcterms:rights>
+    <pgterms:downloads rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">49291</pgterms:downloads>
+    <dcterms:creator>
+      <pgterms:agent rdf:about="2009/agents/68">
+        <pgterms:name>Austen, Jane</pgterms:name>
+        <pgterms:birthdate rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">1775</pgterms:birthdate>
+        <pgterms:deathdate rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">1817</pgterms:deathdate>
+        <pgterms:webpage rdf:resource="https://en.wikipedia.org/wiki/Jane_Austen"/>
+      </pgterms:agent>
+    </dcterms:creator>
+    <marcrel:ill rdf:resource="2009/agents/3948"/>
+    <dcterms:title>Pride and Prejudice</dcterms:title>
+    <dcterms:language>
+      <rdf:Description rdf:nodeID="N1">
+        <rdf:value rdf:datatype="http://purl.org/dc/terms/RFC4646">en</rdf:value>
+      </rdf:Description>
+    </dcterms:language>
+    <dcterms:subject>
+      <rdf:Description rdf:nodeID="N2">
+        <dcam:memberOf rdf:resource="http://purl.org/dc/terms/LCSH"/>
+        <rdf:value>Courtship -- Fiction</rdf:value>
+      </rdf:Description>
+    </dcterms:subject>
+    <dcterms:subject>
+      <rdf:Description rdf:nodeID="N3">
+        <rdf:value>PR</rdf:value>
+        <dcam:memberOf rdf:resource="http://purl.org/dc/terms/LCC"/>
+      </rdf:Description>
+    </dcterms:subject>
+    <pgterms:bookshelf>
+      <rdf:Description rdf:nodeID="N4">
+        <dcam:memberOf rdf:resource="2009/pgterms/Bookshelf"/>
+        <rdf:value>Best Books Ever Listings</rdf:value>
+      </rdf:Description>
+    </pgterms:bookshelf>
+    <dcterms:hasFormat>
+      <pgterms:file rdf:about="https://www.gutenberg.org/files/1342/1342-0.zip">
+        <dcterms:extent rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">264297</dcterms:extent>
+        <dcterms:format>
+          <rdf:Description rdf:nodeID="N5">
+            <rdf:value rdf:datatype="http://purl.org/dc/terms/IMT">text/plain; charset=utf-8</rdf:value>
+            <dcam:memberOf rdf:resource="http://purl.org/dc/terms/IMT"/>
+          </rdf:Description>
+        </dcterms:format>
+        <dcterms:format>
+          <rdf:Description rdf:nodeID="N6">
+            <rdf:value rdf:datatype="http://purl.org/dc/terms/IMT">application/zip</rdf:value>
+            <dcam:memberOf rdf:resource="http://purl.org/dc/terms/IMT"/>
+          </rdf:Description>
+        </dcterms:format>
+        <dcterms:modified rdf:datatype="http://www.w3.org/2001/XMLSchema#dateTime">2023-06-01T01:29:54</dcterms:modified>
+        <dcterms:isFormatOf rdf:resource="ebooks/1342"/>
+      </pgterms:file>
+    </dcterms:hasFormat>
+  </pgterms:ebook>
+  <pgterms:agent rdf:about="2009/agents/3948">
+    <pgterms:name>Thomson, Hugh</pgterms:name>
+    <pgterms:birthdate rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">1860</pgterms:birthdate>
+    <pgterms:deathdate rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">1920</pgterms:deathdate>
+  </pgterms:agent>
+</rdf:RDF>
+"#;
+
+    #[test]
+    fn test_parse_rdf() {
+        let entry = parse_rdf(PRIDE_AND_PREJUDICE).unwrap();
+        assert_eq!(entry.id, 1342);
+        assert_eq!(entry.title, "Pride and Prejudice");
+        assert_eq!(
+            entry.creators,
+            vec![
+                Creator {
+                    name: "Austen, Jane".to_string(),
+                    birth_year: Some(1775),
+                    death_year: Some(1817),
+                    role: "aut".to_string(),
+                },
+                Creator {
+                    name: "Thomson, Hugh".to_string(),
+                    birth_year: Some(1860),
+                    death_year: Some(1920),
+                    role: "ill".to_string(),
+                },
+            ]
+        );
+        assert_eq!(entry.authors().count(), 1);
+        assert_eq!(entry.subjects, vec!["Courtship -- Fiction"]);
+        assert_eq!(entry.lcc, vec!["PR"]);
+        assert_eq!(entry.languages, vec!["en"]);
+        assert_eq!(entry.bookshelves, vec!["Best Books Ever Listings"]);
+        assert_eq!(entry.rights.as_deref(), Some("Public domain in the USA."));
+        assert_eq!(entry.issued.as_deref(), Some("1998-06-01"));
+        assert_eq!(entry.downloads, Some(49291));
+        assert_eq!(
+            entry.formats,
+            vec![CatalogFormat {
+                url: "https://www.gutenberg.org/files/1342/1342-0.zip".to_string(),
+                media_types: vec![
+                    "text/plain; charset=utf-8".to_string(),
+                    "application/zip".to_string()
+                ],
+                size: Some(264297),
+            }]
+        );
+        assert!(parse_rdf("<rdf:RDF></rdf:RDF>").is_err());
+    }
+
+    #[test]
+    fn test_read_catalog() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rdf-files.tar.bz2");
+        let mut builder = tar::Builder::new(BzEncoder::new(
+            File::create(&path).unwrap(),
+            bzip2::Compression::fast(),
+        ));
+        let files = [
+            ("cache/epub/1342/pg1342.rdf", PRIDE_AND_PREJUDICE),
+            ("cache/epub/0/pg0.rdf", "<rdf:RDF><pgterms:ebook"),
+            ("cache/epub/README", "not an rdf file"),
+        ];
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let catalog = read_catalog(&path).unwrap();
+        assert_eq!(
+            catalog.entries.keys().copied().collect::<Vec<_>>(),
+            vec![1342]
+        );
+        assert_eq!(catalog.entries[&1342].title, "Pride and Prejudice");
+        assert_eq!(catalog.errors.len(), 1);
+        assert_eq!(catalog.errors[0].0, "cache/epub/0/pg0.rdf");
+    }
+}
@@ -1,32 +1,384 @@
+use anyhow::{anyhow, Error};
 use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use crate::parser::FormatPolicy;
+
+const CONFIG_FILE: &str = "config.toml";
+// Folder under the XDG config dirs, e.g. ~/.config/guten/config.toml
+const APP_DIR: &str = "guten";
+/// Points at the config file, like `--config`
+pub const CONFIG_ENV: &str = "GUTEN_CONFIG";
+/// The commented config written by `guten config init`, its values are
+/// the same as [`Config::default`]
+pub const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+const DEFAULT_URL: &str = "https://gutenberg.pglaf.org/";
+const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 8;
+/// Prefix of the variables that override single keys, nested keys are
+/// joined with `__`, e.g. GUTEN_DOWNLOAD_SETTINGS__CONCURRENCY=4
+pub const ENV_PREFIX: &str = "GUTEN_";
+
+// Unknown keys are errors so that a typo does not silently fall back to
+// the default
 #[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub url: String,
+    pub mirrors: Option<Vec<String>>,
+    pub mirror_max_failures: Option<usize>,
     pub whitelist: Vec<String>,
     pub download_path: Option<String>,
     pub download_settings: DownloadSettings,
     pub crawler_settings: CrawlerSettings,
+    pub formats: FormatPolicy,
     pub debug: Option<bool>,
+    pub user_agent: Option<String>,
+    pub contact: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            url: DEFAULT_URL.to_string(),
+            mirrors: None,
+            mirror_max_failures: None,
+            // The whole collection
+            whitelist: (0..10).map(|digit| format!("{}/", digit)).collect(),
+            download_path: None,
+            download_settings: DownloadSettings::default(),
+            crawler_settings: CrawlerSettings::default(),
+            formats: FormatPolicy::default(),
+            debug: None,
+            user_agent: None,
+            contact: None,
+        }
+    }
+}
+
+impl Config {
+    /// Every problem at once, each starting with the key it is about.
+    /// Empty when the config is usable.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        match self.local_root() {
+            Some(root) if !root.is_dir() => problems.push(format!(
+                "url: local mirror {} is not a directory",
+                root.display()
+            )),
+            Some(_) => {}
+            None => {
+                if let Err(e) = check_http_url(&self.url) {
+                    problems.push(format!("url: {:?} {}", self.url, e));
+                }
+            }
+        }
+        for mirror in self.mirrors.iter().flatten() {
+            if let Err(e) = check_http_url(mirror) {
+                problems.push(format!("mirrors: {:?} {}", mirror, e));
+            }
+        }
+        for entry in &self.whitelist {
+            if entry.is_empty() || entry.contains("://") || entry.starts_with('/') {
+                problems.push(format!(
+                    "whitelist: {:?} must be a path relative to the mirror root, e.g. \"1/\"",
+                    entry
+                ));
+            } else if entry.split('/').any(|segment| segment == "..") {
+                problems.push(format!("whitelist: {:?} must not contain \"..\"", entry));
+            }
+        }
+        let download = &self.download_settings;
+        if download.concurrency == 0 {
+            problems.push("download_settings.concurrency: must be greater than 0".to_string());
+        }
+        if download.max_bytes_per_sec == Some(0) {
+            problems.push(
+                "download_settings.max_bytes_per_sec: must be greater than 0, leave it out for no limit"
+                    .to_string(),
+            );
+        }
+        if download.max_connections_per_host == Some(0) {
+            problems.push(
+                "download_settings.max_connections_per_host: must be greater than 0, leave it out for no limit"
+                    .to_string(),
+            );
+        }
+        if download.retry == Some(0) {
+            problems.push("download_settings.retry: must be at least 1 attempt".to_string());
+        }
+        let crawler = &self.crawler_settings;
+        if crawler.concurrency_limit == Some(0) {
+            problems.push("crawler_settings.concurrency_limit: must be greater than 0".to_string());
+        }
+        if crawler
+            .delay
+            .is_some_and(|delay| !delay.is_finite() || delay < 0.0)
+        {
+            problems.push("crawler_settings.delay: must be 0 or more seconds".to_string());
+        }
+        if crawler.retry == Some(0) {
+            problems.push("crawler_settings.retry: must be at least 1 attempt".to_string());
+        }
+        let patterns = [
+            (
+                "crawler_settings.exclude",
+                crawler.exclude.as_deref().unwrap_or_default(),
+            ),
+            (
+                "formats.include_patterns",
+                self.formats.include_patterns.sources(),
+            ),
+            (
+                "formats.exclude_patterns",
+                self.formats.exclude_patterns.sources(),
+            ),
+        ];
+        for (key, patterns) in patterns {
+            for pattern in patterns {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    problems.push(format!("{}: {:?} is not a valid glob, {}", key, pattern, e));
+                }
+            }
+        }
+        if self
+            .user_agent
+            .as_deref()
+            .is_some_and(|agent| agent.trim().is_empty())
+        {
+            problems
+                .push("user_agent: must not be empty, leave it out for the default".to_string());
+        }
+        problems
+    }
+
+    /// The directory to read from when `url` is a `file://` url or a
+    /// plain path, e.g. a local rsync copy of the collection
+    pub fn local_root(&self) -> Option<PathBuf> {
+        if let Some(path) = self.url.strip_prefix("file://") {
+            return Some(PathBuf::from(path));
+        }
+        if self.url.contains("://") {
+            return None;
+        }
+        Some(PathBuf::from(&self.url))
+    }
+
+    /// The User-Agent sent with every request, with the contact appended
+    /// so mirror operators know who to reach
+    pub fn user_agent(&self) -> String {
+        let agent = match &self.user_agent {
+            Some(agent) => agent.clone(),
+            None => format!("guten-rs/{}", env!("CARGO_PKG_VERSION")),
+        };
+        match &self.contact {
+            Some(contact) => format!("{} (+{})", agent, contact),
+            None => agent,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct DownloadSettings {
     pub concurrency: usize,
+    /// Combined rate of all downloads, unlimited when unset
+    pub max_bytes_per_sec: Option<u64>,
+    /// Open transfers per mirror host, unlimited when unset
+    pub max_connections_per_host: Option<usize>,
+    /// Attempts per file on each mirror, 3 by default
+    pub retry: Option<u8>,
+    /// Check that downloaded zips open, on by default
+    pub verify: Option<bool>,
+    /// Also decompress every zip entry and check its CRC, off by default
+    pub verify_crc: Option<bool>,
+    /// Hardlink files from a local mirror instead of copying, on by default
+    pub hardlink: Option<bool>,
+    /// Where corrupt downloads are moved, defaults to .cache/quarantine
+    pub quarantine_path: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        DownloadSettings {
+            concurrency: DEFAULT_DOWNLOAD_CONCURRENCY,
+            max_bytes_per_sec: None,
+            max_connections_per_host: None,
+            retry: None,
+            verify: None,
+            verify_crc: None,
+            hardlink: None,
+            quarantine_path: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
 pub struct CrawlerSettings {
     pub concurrency_limit: Option<usize>,
-    pub delay: Option<u64>,
+    /// Seconds, fractions such as 0.5 are allowed
+    pub delay: Option<f64>,
     pub retry: Option<u8>,
+    pub checkpoint_interval: Option<u64>,
+    pub exclude: Option<Vec<String>>,
 }
 
-// find a default config file
-pub fn get_config() -> Config {
-    let config_file = std::fs::read_to_string("config.toml").expect("Failed to read config file");
-    let config: Config = toml::from_str(&config_file).expect("Failed to parse config file");
-    config
+/// Reads the config from `path`, or the first file found by
+/// [`config_search_paths`], then applies the GUTEN_* overrides. Without
+/// any config file the defaults are used.
+pub fn load_config(path: Option<&Path>) -> Result<Config, Error> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match find_config_file() {
+            Some(path) => path,
+            None => {
+                log::info!(
+                    "No config file found, using the defaults. Write one with `guten config init`"
+                );
+                return parse_config("", "default config", env::vars());
+            }
+        },
+    };
+    let text = fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Failed to read config file {}: {}", path.display(), e))?;
+    parse_config(&text, &path.display().to_string(), env::vars())
+}
+
+/// Where to look for a config file, in order: $GUTEN_CONFIG, ./config.toml,
+/// $XDG_CONFIG_HOME/guten/config.toml (~/.config by default), then each of
+/// $XDG_CONFIG_DIRS (/etc/xdg by default)
+pub fn config_search_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(path) = env::var_os(CONFIG_ENV) {
+        paths.push(PathBuf::from(path));
+    }
+    paths.push(PathBuf::from(CONFIG_FILE));
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(dir) = config_home {
+        paths.push(dir.join(APP_DIR).join(CONFIG_FILE));
+    }
+    let config_dirs = env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+    for dir in config_dirs.split(':').filter(|dir| !dir.is_empty()) {
+        paths.push(Path::new(dir).join(APP_DIR).join(CONFIG_FILE));
+    }
+    paths
+}
+
+fn find_config_file() -> Option<PathBuf> {
+    // An explicit GUTEN_CONFIG that does not exist is a mistake, not a
+    // reason to pick up some other file
+    if let Some(path) = env::var_os(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    config_search_paths()
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
+/// Writes [`DEFAULT_CONFIG`] to `path`, an existing file is only replaced
+/// with `force`
+pub fn write_default_config(path: &Path, force: bool) -> Result<(), Error> {
+    if path.exists() && !force {
+        return Err(anyhow!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        ));
+    }
+    if let Some(parent) = path.parent() {
+        if parent != Path::new("") {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    fs::write(path, DEFAULT_CONFIG)?;
+    Ok(())
+}
+
+fn check_http_url(url: &str) -> Result<(), String> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        Ok(parsed) => Err(format!(
+            "uses {:?}, only http and https are supported",
+            parsed.scheme()
+        )),
+        Err(e) => Err(format!("is not a valid url, {}", e)),
+    }
+}
+
+/// Parses `text` and applies the GUTEN_* entries of `vars` on top.
+/// `source` names the file in error messages.
+pub fn parse_config(
+    text: &str,
+    source: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Config, Error> {
+    // Syntax errors come with the line and column
+    let mut table: toml::Table = toml::from_str(text).map_err(|e| anyhow!("{}: {}", source, e))?;
+    let mut overrides = Vec::new();
+    for (name, raw) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if name == CONFIG_ENV || key.is_empty() {
+            continue;
+        }
+        let path: Vec<String> = key.to_lowercase().split("__").map(str::to_string).collect();
+        set_key(&mut table, &path, env_value(&raw)).map_err(|e| anyhow!("{}: {}", name, e))?;
+        overrides.push((name, path.join(".")));
+    }
+    // Type errors name the key, e.g. "in `download_settings.concurrency`"
+    let config: Config = table.try_into().map_err(|e: toml::de::Error| {
+        let message = e.to_string();
+        let message = message.trim_end();
+        let culprit = overrides.iter().find(|(_, key)| {
+            let field = key.rsplit('.').next().unwrap_or(key);
+            message.contains(&format!("`{}`", key))
+                || message.contains(&format!("unknown field `{}`", field))
+        });
+        match culprit {
+            Some((name, _)) => anyhow!("{}: {} (set by {})", source, message, name),
+            None => anyhow!("{}: {}", source, message),
+        }
+    })?;
+    let problems = config.validate();
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "{}: invalid config\n  {}",
+            source,
+            problems.join("\n  ")
+        ));
+    }
+    Ok(config)
+}
+
+// Values are read as TOML so numbers, booleans and arrays keep their type,
+// anything that does not parse is taken as a plain string
+fn env_value(raw: &str) -> toml::Value {
+    match toml::from_str::<toml::Table>(&format!("value = {}", raw)) {
+        Ok(mut table) => table.remove("value").unwrap_or_else(|| raw.into()),
+        Err(_) => raw.into(),
+    }
+}
+
+fn set_key(table: &mut toml::Table, path: &[String], value: toml::Value) -> Result<(), Error> {
+    let (last, parents) = path.split_last().ok_or_else(|| anyhow!("empty key"))?;
+    let mut current = table;
+    for (depth, key) in parents.iter().enumerate() {
+        current = current
+            .entry(key.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("`{}` is not a section", path[..=depth].join(".")))?;
+    }
+    current.insert(last.clone(), value);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -44,7 +396,131 @@ concurrency = 8
         "#;
         let config: Config = toml::from_str(config_str).unwrap();
         assert_eq!(config.url, "https://testurl.com");
-        assert_eq!(config.whitelist.len(), 2);
+        assert_eq!(config.whitelist.len(), 1);
         println!("config: {:?}", config);
     }
+
+    #[test]
+    fn test_default_config() {
+        // The template and the defaults in code must agree
+        let config = parse_config(DEFAULT_CONFIG, "default", vars(&[])).unwrap();
+        assert_eq!(format!("{:?}", config), format!("{:?}", Config::default()));
+        let empty = parse_config("", "empty", vars(&[])).unwrap();
+        assert_eq!(format!("{:?}", empty), format!("{:?}", Config::default()));
+        // The example in the repository stays loadable
+        parse_config(include_str!("../config.toml"), "config.toml", vars(&[])).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let config_str = r#"
+url = "ftp://mirror.example/"
+mirrors = ["not a url"]
+whitelist = ["5/", "/2", "https://mirror.example/3/", "4/../.."]
+
+[download_settings]
+concurrency = 0
+max_connections_per_host = 0
+retry = 0
+
+[crawler_settings]
+delay = -1.5
+retry = 0
+exclude = ["[old"]
+"#;
+        let error = parse_config(config_str, "config.toml", vars(&[]))
+            .unwrap_err()
+            .to_string();
+        for key in [
+            "url:",
+            "mirrors:",
+            "whitelist: \"/2\"",
+            "whitelist: \"https://mirror.example/3/\"",
+            "whitelist: \"4/../..\"",
+            "download_settings.concurrency:",
+            "download_settings.max_connections_per_host:",
+            "download_settings.retry:",
+            "crawler_settings.delay:",
+            "crawler_settings.retry:",
+            "crawler_settings.exclude:",
+        ] {
+            assert!(error.contains(key), "{} missing from {}", key, error);
+        }
+        assert!(!error.contains("\"5/\""), "{}", error);
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let config_str = r#"
+url = "https://testurl.com"
+whitelist = ["/0"]
+
+[download_settings]
+concurrency = 8
+
+[crawler_settings]
+delay = 5
+"#;
+        let config = parse_config(
+            config_str,
+            "config.toml",
+            vars(&[
+                ("GUTEN_URL", "https://mirror.example/"),
+                ("GUTEN_DOWNLOAD_SETTINGS__CONCURRENCY", "2"),
+                ("GUTEN_WHITELIST", r#"["1/", "2/"]"#),
+                ("GUTEN_CRAWLER_SETTINGS__EXCLUDE", r#"["old"]"#),
+                ("GUTEN_CONFIG", "elsewhere.toml"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.url, "https://mirror.example/");
+        assert_eq!(config.download_settings.concurrency, 2);
+        assert_eq!(config.whitelist, vec!["1/", "2/"]);
+        assert_eq!(config.crawler_settings.delay, Some(5.0));
+        assert_eq!(
+            config.crawler_settings.exclude,
+            Some(vec!["old".to_string()])
+        );
+
+        let error = parse_config(
+            config_str,
+            "config.toml",
+            vars(&[("GUTEN_DOWNLOAD_SETTINGS__CONCURRENCY", "many")]),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("download_settings.concurrency"), "{}", error);
+        assert!(
+            error.contains("GUTEN_DOWNLOAD_SETTINGS__CONCURRENCY"),
+            "{}",
+            error
+        );
+
+        let error = parse_config(
+            config_str,
+            "config.toml",
+            vars(&[("GUTEN_CRAWLER_SETTINGS__DELAYY", "1")]),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("unknown field `delayy`"), "{}", error);
+        assert!(
+            error.contains("GUTEN_CRAWLER_SETTINGS__DELAYY"),
+            "{}",
+            error
+        );
+
+        let error = parse_config(config_str, "config.toml", vars(&[("GUTEN_URL__X", "1")]))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("`url` is not a section"), "{}", error);
+    }
 }
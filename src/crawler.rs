@@ -0,0 +1,817 @@
+use anyhow::{anyhow, Error};
+use futures::stream::{FuturesUnordered, StreamExt};
+use indicatif::ProgressBar;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::ebook_ids::ebook_dir;
+use crate::manifest::{format_listing_time, ManifestEntry};
+use crate::mirrors::MirrorPool;
+use crate::parser::{
+    filter_href, parse_listing, ExtractedLinks, FormatPolicy, ListingEntry, PathExcludes,
+};
+use crate::retry::{fetch_with_retry, is_retryable_status, RetryPolicy};
+use crate::robots::Robots;
+use crate::shutdown::{Interrupted, Shutdown};
+
+// Defaults for the keys left out of [crawler_settings]
+const DEFAULT_CONCURRENCY: usize = 4;
+const DEFAULT_DELAY_SECS: f64 = 0.0;
+const DEFAULT_RETRY: u8 = 3;
+const DEFAULT_CHECKPOINT_SECS: u64 = 30;
+// Legacy trees that duplicate the numbered hierarchy
+const DEFAULT_EXCLUDES: [&str; 2] = ["old", "etext[0-9][0-9]"];
+
+/// Hooks the crawler calls while it runs, so callers can report progress
+/// however they like. Every method defaults to doing nothing.
+pub trait CrawlProgress: Send + Sync {
+    /// Called once before crawling, with the files already known from a resumed state
+    fn start(&self, _known_files: usize) {}
+    /// Called after a directory listing has been processed
+    fn directory_done(&self, _url: &str, _new_files: usize) {}
+    /// Called when a directory could not be listed
+    fn error(&self, _url: &str, _error: &Error) {}
+    /// Called once the crawl is complete
+    fn finish(&self, _total_files: usize) {}
+}
+
+impl CrawlProgress for () {}
+
+impl CrawlProgress for ProgressBar {
+    fn start(&self, known_files: usize) {
+        self.inc(known_files as u64);
+    }
+    fn directory_done(&self, _url: &str, new_files: usize) {
+        self.inc(new_files as u64);
+    }
+    fn error(&self, url: &str, error: &Error) {
+        self.println(format!("Error crawling {}: {}", url, error));
+    }
+    fn finish(&self, _total_files: usize) {
+        self.finish_with_message("Done!");
+    }
+}
+
+/// Everything needed to pick a crawl back up after it was stopped.
+/// `queue` also holds directories that were in flight at checkpoint time,
+/// and `visited` only holds directories whose listing was fully processed.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CrawlState {
+    pub queue: Vec<String>,
+    pub visited: HashSet<String>,
+    pub files: Vec<ManifestEntry>,
+    #[serde(default)]
+    pub excluded: Vec<String>,
+    /// Directories that could not be listed, queued again on resume
+    #[serde(default)]
+    pub failed: Vec<String>,
+}
+
+/// The result of a finished crawl
+#[derive(Debug, Default)]
+pub struct CrawlSummary {
+    pub files: Vec<ManifestEntry>,
+    /// Directories that were not descended into because of the exclude list
+    pub excluded: Vec<String>,
+    /// Directories that could not be listed, their files are missing.
+    /// The checkpoint is kept so `--resume` tries them again.
+    pub failed: Vec<String>,
+}
+
+impl CrawlState {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    // Write to a temporary file first so a kill mid-write keeps the old state
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut out_buf = std::io::BufWriter::new(fs::File::create(&tmp_path)?);
+            serde_json::to_writer(&mut out_buf, self)?;
+            out_buf.flush()?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Walks the mirror's directory listings starting from the whitelist
+/// and collects every file that survives [`filter_href`].
+pub struct Crawler {
+    client: Client,
+    throttle: HostThrottle,
+    base_url: String,
+    /// Set when `base_url` is a local directory, listings come from the
+    /// filesystem instead of HTTP
+    local_root: Option<PathBuf>,
+    mirrors: MirrorPool,
+    // robots.txt of each mirror host, fetched before its first listing
+    robots: tokio::sync::Mutex<HashMap<String, Arc<Robots>>>,
+    user_agent: String,
+    whitelist: Vec<String>,
+    formats: FormatPolicy,
+    excludes: PathExcludes,
+    concurrency: usize,
+    retry: RetryPolicy,
+    checkpoint_path: Option<PathBuf>,
+    checkpoint_every: Duration,
+    progress: Box<dyn CrawlProgress>,
+    shutdown: Shutdown,
+}
+
+impl Crawler {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let settings = &config.crawler_settings;
+        let user_agent = config.user_agent();
+        Ok(Crawler {
+            client: Client::builder().user_agent(&user_agent).build()?,
+            throttle: HostThrottle::new(
+                Duration::try_from_secs_f64(settings.delay.unwrap_or(DEFAULT_DELAY_SECS))
+                    .unwrap_or(Duration::ZERO),
+            ),
+            base_url: config.url.clone(),
+            local_root: config.local_root(),
+            mirrors: MirrorPool::from_config(config),
+            robots: tokio::sync::Mutex::new(HashMap::new()),
+            user_agent,
+            whitelist: config.whitelist.clone(),
+            formats: config.formats.clone(),
+            excludes: match &settings.exclude {
+                Some(patterns) => PathExcludes::new(patterns),
+                None => PathExcludes::new(&DEFAULT_EXCLUDES.map(String::from)),
+            },
+            concurrency: settings
+                .concurrency_limit
+                .unwrap_or(DEFAULT_CONCURRENCY)
+                .max(1),
+            retry: RetryPolicy::new(settings.retry.unwrap_or(DEFAULT_RETRY) as usize),
+            checkpoint_path: None,
+            checkpoint_every: Duration::from_secs(
+                settings
+                    .checkpoint_interval
+                    .unwrap_or(DEFAULT_CHECKPOINT_SECS),
+            ),
+            progress: Box::new(()),
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    pub fn with_progress(mut self, progress: impl CrawlProgress + 'static) -> Self {
+        self.progress = Box::new(progress);
+        self
+    }
+
+    /// Stop when `shutdown` triggers. Listings in flight are dropped and
+    /// go back into the saved queue, and `run` returns [`Interrupted`].
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Save the crawl state to `path` at the configured interval.
+    /// The file is removed once the crawl completes.
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self
+    }
+
+    /// A fresh state that starts at the whitelisted directories
+    pub fn initial_state(&self) -> CrawlState {
+        CrawlState {
+            queue: self
+                .whitelist
+                .iter()
+                .map(|url| handle_slashes(&self.base_url, url))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// A fresh state that only visits the folders of the given ebooks
+    pub fn state_for_ids(&self, ids: &[u32]) -> CrawlState {
+        CrawlState {
+            queue: ids
+                .iter()
+                .rev()
+                .map(|&id| handle_slashes(&self.base_url, &ebook_dir(id)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Crawls the whole whitelist from scratch
+    pub async fn crawl(&self) -> Result<CrawlSummary, Error> {
+        self.run(self.initial_state()).await
+    }
+
+    /// Crawls until the queue in `state` is exhausted
+    pub async fn run(&self, state: CrawlState) -> Result<CrawlSummary, Error> {
+        let CrawlState {
+            mut queue,
+            mut visited,
+            mut files,
+            mut excluded,
+            failed,
+        } = state;
+        // Another chance for the directories a previous run could not list
+        queue.extend(failed);
+        let mut failed = Vec::new();
+        self.progress.start(files.len());
+        // Directories that have been handed out but not finished yet
+        let mut pending: HashSet<String> = HashSet::new();
+        let mut in_flight = FuturesUnordered::new();
+        let mut last_checkpoint = Instant::now();
+
+        loop {
+            // Keep up to `concurrency` directory listings in flight
+            while in_flight.len() < self.concurrency {
+                let Some(url) = queue.pop() else {
+                    break;
+                };
+                // Only process if we haven't visited this URL
+                if visited.contains(&url) || pending.contains(&url) {
+                    continue;
+                }
+                pending.insert(url.clone());
+                in_flight.push(async move {
+                    let result = self.crawl_directory(&url).await;
+                    (url, result)
+                });
+            }
+
+            let next = tokio::select! {
+                next = in_flight.next() => next,
+                _ = self.shutdown.wait() => {
+                    // Listings in flight are still in `pending` and get saved with the queue
+                    let state = CrawlState {
+                        queue: queue.iter().chain(pending.iter()).cloned().collect(),
+                        visited,
+                        files,
+                        excluded,
+                        failed,
+                    };
+                    self.checkpoint(&state);
+                    return Err(Interrupted.into());
+                }
+            };
+            let Some((url, result)) = next else {
+                // Nothing in flight and nothing queued
+                break;
+            };
+            pending.remove(&url);
+            match result {
+                Ok((extracted, listed)) => {
+                    self.progress.directory_done(&url, listed.len());
+                    for directory in &extracted.excluded {
+                        log::info!("Skipping {}: excluded", directory);
+                    }
+                    // Add new links to the queue
+                    queue.extend(extracted.directories);
+                    files.extend(listed);
+                    excluded.extend(extracted.excluded);
+                    visited.insert(url);
+                }
+                Err(e) => {
+                    self.progress.error(&url, &e);
+                    failed.push(url);
+                }
+            }
+
+            if last_checkpoint.elapsed() >= self.checkpoint_every {
+                let state = CrawlState {
+                    queue: queue.iter().chain(pending.iter()).cloned().collect(),
+                    visited: visited.clone(),
+                    files: files.clone(),
+                    excluded: excluded.clone(),
+                    failed: failed.clone(),
+                };
+                self.checkpoint(&state);
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        if failed.is_empty() {
+            // The crawl is complete, a later resume should start over
+            if let Some(path) = &self.checkpoint_path {
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+        } else {
+            self.checkpoint(&CrawlState {
+                queue: Vec::new(),
+                visited,
+                files: files.clone(),
+                excluded: excluded.clone(),
+                failed: failed.clone(),
+            });
+        }
+        self.progress.finish(files.len());
+        Ok(CrawlSummary {
+            files,
+            excluded,
+            failed,
+        })
+    }
+
+    fn checkpoint(&self, state: &CrawlState) {
+        if let Some(path) = &self.checkpoint_path {
+            if let Err(e) = state.save(path) {
+                log::warn!("Failed to save crawl state: {}", e);
+            }
+        }
+    }
+
+    /// The robots.txt of the host serving `url`, fetched on first use
+    async fn robots_for(&self, url: &str) -> Arc<Robots> {
+        let origin = match Url::parse(url) {
+            Ok(parsed) => parsed.origin().ascii_serialization(),
+            Err(_) => return Arc::new(Robots::allow_all()),
+        };
+        // Held while fetching, so each host is only asked once
+        let mut cache = self.robots.lock().await;
+        if let Some(robots) = cache.get(&origin) {
+            return robots.clone();
+        }
+        let robots = Arc::new(self.fetch_robots(url).await);
+        cache.insert(origin, robots.clone());
+        robots
+    }
+
+    /// Reads robots.txt from the host of `url`. A missing or unreachable
+    /// robots.txt allows everything. Its Crawl-delay raises the throttle
+    /// delay for the host if it is longer than the configured one.
+    async fn fetch_robots(&self, url: &str) -> Robots {
+        let Ok(mut robots_url) = Url::parse(url) else {
+            return Robots::allow_all();
+        };
+        robots_url.set_path("/robots.txt");
+        robots_url.set_query(None);
+        let robots_url = robots_url.to_string();
+
+        let robots = match fetch_with_retry(&self.retry, &robots_url, || async {
+            self.throttle.wait(&robots_url).await;
+            self.client.get(&robots_url).send().await
+        })
+        .await
+        {
+            Ok(res) if res.status().is_success() => match res.text().await {
+                Ok(text) => Robots::parse(&text, &self.user_agent),
+                Err(e) => {
+                    log::warn!("Failed to read {}: {}", robots_url, e);
+                    Robots::allow_all()
+                }
+            },
+            Ok(_) => Robots::allow_all(),
+            Err(e) => {
+                log::warn!("Failed to fetch {}: {}", robots_url, e);
+                Robots::allow_all()
+            }
+        };
+        if let Some(delay) = robots.crawl_delay() {
+            log::info!("{} asks for a crawl delay of {:?}", robots_url, delay);
+            self.throttle.raise_delay(&robots_url, delay);
+        }
+        robots
+    }
+
+    // Returns the subdirectories to crawl next and the files in this one.
+    // `url` is always under the primary url, the listing is fetched from
+    // whichever mirror is healthy and moves on to the next one on failure.
+    // A mirror whose robots.txt disallows the directory is not asked.
+    async fn crawl_directory(
+        &self,
+        url: &str,
+    ) -> Result<(ExtractedLinks, Vec<ManifestEntry>), Error> {
+        if let Some(root) = &self.local_root {
+            let relative = url.strip_prefix(&self.base_url).unwrap_or(url);
+            let entries = list_local_dir(&root.join(relative.trim_start_matches('/'))).await?;
+            return Ok(extract_entries(
+                &self.base_url,
+                url,
+                entries,
+                &self.formats,
+                &self.excludes,
+            ));
+        }
+        let mut last_error = anyhow!("No mirror available for {}", url);
+        let mut disallowed = 0;
+        let mut tried: Vec<String> = Vec::new();
+        for _ in 0..self.mirrors.len() {
+            let mirror = self.mirrors.pick_untried(&tried);
+            tried.push(mirror.to_string());
+            let target = self.mirrors.rewrite(url, mirror)?;
+            let robots = self.robots_for(&target).await;
+            if !robots.is_allowed(url_path(&target)) {
+                log::info!("Skipping {}: disallowed by robots.txt", target);
+                disallowed += 1;
+                continue;
+            }
+            let res = match fetch_with_retry(&self.retry, &target, || async {
+                self.throttle.wait(&target).await;
+                self.client.get(&target).send().await
+            })
+            .await
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    self.mirrors.report_failure(mirror);
+                    last_error = e.into();
+                    continue;
+                }
+            };
+            if is_retryable_status(res.status()) {
+                self.mirrors.report_failure(mirror);
+                last_error = anyhow!("{} returned {}", target, res.status());
+                continue;
+            }
+            if !res.status().is_success() {
+                // Not the mirror's fault, e.g. a 404
+                return Ok(empty_listing());
+            }
+            let body = match res.text().await {
+                Ok(body) => body,
+                Err(e) => {
+                    self.mirrors.report_failure(mirror);
+                    last_error = e.into();
+                    continue;
+                }
+            };
+            self.mirrors.report_success(mirror);
+
+            // Extract both directories and files
+            let (extracted, mut listed) =
+                extract_links(&self.base_url, url, &body, &self.formats, &self.excludes);
+            listed.retain(|file| {
+                let allowed = self
+                    .mirrors
+                    .rewrite(&file.url, mirror)
+                    .is_ok_and(|target| robots.is_allowed(url_path(&target)));
+                if !allowed {
+                    log::info!("Skipping {}: disallowed by robots.txt", file.url);
+                }
+                allowed
+            });
+            return Ok((extracted, listed));
+        }
+        if disallowed == self.mirrors.len() {
+            // Every mirror asks us to stay out, like a 404
+            return Ok(empty_listing());
+        }
+        Err(last_error)
+    }
+}
+
+fn empty_listing() -> (ExtractedLinks, Vec<ManifestEntry>) {
+    (
+        ExtractedLinks {
+            directories: Vec::new(),
+            files: Vec::new(),
+            excluded: Vec::new(),
+        },
+        Vec::new(),
+    )
+}
+
+fn extract_links(
+    base_url: &str,
+    current_link: &str,
+    html: &str,
+    policy: &FormatPolicy,
+    excludes: &PathExcludes,
+) -> (ExtractedLinks, Vec<ManifestEntry>) {
+    extract_entries(
+        base_url,
+        current_link,
+        parse_listing(html),
+        policy,
+        excludes,
+    )
+}
+
+// Lists a local folder the way the mirror's autoindex would, folders
+// get a trailing '/' and the mtime is rounded to the minute
+async fn list_local_dir(dir: &Path) -> Result<Vec<ListingEntry>, Error> {
+    let mut entries = Vec::new();
+    let mut read_dir = match tokio::fs::read_dir(dir).await {
+        Ok(read_dir) => read_dir,
+        // Same as a 404 from the mirror
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(anyhow!("{}: {}", dir.display(), e)),
+    };
+    while let Some(entry) = read_dir.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        // Follows symlinks, rsync trees may use them
+        let meta = tokio::fs::metadata(entry.path()).await?;
+        let modified = meta.modified().ok().map(format_listing_time);
+        if meta.is_dir() {
+            entries.push(ListingEntry {
+                href: format!("{}/", name),
+                size: None,
+                modified,
+            });
+        } else {
+            entries.push(ListingEntry {
+                href: name,
+                size: Some(meta.len()),
+                modified,
+            });
+        }
+    }
+    // read_dir order is arbitrary, listings are sorted by name
+    entries.sort_by(|a, b| a.href.cmp(&b.href));
+    Ok(entries)
+}
+
+fn extract_entries(
+    base_url: &str,
+    current_link: &str,
+    entries: Vec<ListingEntry>,
+    policy: &FormatPolicy,
+    excludes: &PathExcludes,
+) -> (ExtractedLinks, Vec<ManifestEntry>) {
+    let href_vec: Vec<&str> = entries.iter().map(|entry| entry.href.as_str()).collect();
+    let mut extracted = filter_href(current_link, href_vec, policy, excludes);
+    // filter_href only sees folder names, check the full path as well
+    let (excluded, directories): (Vec<String>, Vec<String>) =
+        extracted.directories.into_iter().partition(|directory| {
+            let path = directory.strip_prefix(base_url).unwrap_or(directory);
+            excludes.is_excluded(path)
+        });
+    extracted.directories = directories;
+    extracted.excluded.extend(excluded);
+    // Attach the listing columns back onto the files that survived filtering
+    let by_href: HashMap<&str, _> = entries
+        .iter()
+        .map(|entry| (entry.href.as_str(), entry))
+        .collect();
+    let files = extracted
+        .files
+        .iter()
+        .map(|url| {
+            let entry = url
+                .strip_prefix(current_link)
+                .and_then(|href| by_href.get(href));
+            ManifestEntry::new(
+                base_url,
+                url,
+                entry.and_then(|e| e.size),
+                entry.and_then(|e| e.modified.clone()),
+            )
+        })
+        .collect();
+    (extracted, files)
+}
+
+fn url_path(url: &str) -> &str {
+    match url.find("://") {
+        Some(scheme_end) => {
+            let rest = &url[scheme_end + 3..];
+            rest.find('/').map(|pos| &rest[pos..]).unwrap_or("/")
+        }
+        None => url,
+    }
+}
+
+fn handle_slashes(base_url: &str, url: &str) -> String {
+    if url.starts_with('/') && base_url.ends_with('/') {
+        let url = url.strip_prefix('/').unwrap();
+        format!("{}{}", base_url, url)
+    } else if url.starts_with('/') ^ base_url.ends_with('/') {
+        format!("{}{}", base_url, url)
+    } else {
+        format!("{}/{}", base_url, url)
+    }
+}
+
+/// Spaces out requests to the same host by at least `delay`.
+/// Each caller reserves the next free slot for its host, so concurrent
+/// fetches queue up behind each other instead of bursting.
+struct HostThrottle {
+    delay: Duration,
+    next_slot: Mutex<HashMap<String, Instant>>,
+    // Hosts that asked for a longer delay, e.g. through robots.txt
+    host_delay: Mutex<HashMap<String, Duration>>,
+}
+
+pub(crate) fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+impl HostThrottle {
+    fn new(delay: Duration) -> Self {
+        HostThrottle {
+            delay,
+            next_slot: Mutex::new(HashMap::new()),
+            host_delay: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn raise_delay(&self, url: &str, delay: Duration) {
+        if delay > self.delay {
+            self.host_delay.lock().unwrap().insert(host_of(url), delay);
+        }
+    }
+
+    async fn wait(&self, url: &str) {
+        let host = host_of(url);
+        let delay = match self.host_delay.lock().unwrap().get(&host) {
+            Some(&delay) => delay,
+            None => self.delay,
+        };
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = match next_slot.get(&host) {
+                Some(&next) if next > now => next,
+                _ => now,
+            };
+            next_slot.insert(host, slot + delay);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{serve, UNREACHABLE_URL};
+
+    #[test]
+    fn test_handle_slashes() {
+        let base = "https://abc.0/";
+        assert_eq!(handle_slashes(base, "1/"), "https://abc.0/1/");
+        assert_eq!(handle_slashes(base, "/1/"), "https://abc.0/1/");
+        assert_eq!(handle_slashes("https://abc.0", "1/"), "https://abc.0/1/");
+        assert_eq!(handle_slashes("https://abc.0", "/1/"), "https://abc.0/1/");
+    }
+
+    #[test]
+    fn test_url_path() {
+        assert_eq!(url_path("https://abc.0/1/2/"), "/1/2/");
+        assert_eq!(url_path("https://abc.0"), "/");
+    }
+
+    #[test]
+    fn test_extract_links() {
+        let html = r#"<pre><a href="../">Parent Directory</a>
+<a href="1.txt">1.txt</a>     2020-01-01 00:00  10K
+<a href="1.zip">1.zip</a>     2020-01-01 00:00   4K
+<a href="2/">2/</a>           2020-01-01 00:00    -
+<a href="3/">3/</a>           2020-01-01 00:00    -
+<a href="old/">old/</a>       2020-01-01 00:00    -
+</pre>"#;
+        let (extracted, files) = extract_links(
+            "https://abc.0/",
+            "https://abc.0/1/",
+            html,
+            &FormatPolicy::default(),
+            &PathExcludes::new(&["old".to_string(), "1/3".to_string()]),
+        );
+        assert_eq!(extracted.directories, vec!["https://abc.0/1/2/"]);
+        assert_eq!(
+            extracted.excluded,
+            vec!["https://abc.0/1/old/", "https://abc.0/1/3/"]
+        );
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].url, "https://abc.0/1/1.zip");
+        assert_eq!(files[0].path, "1/1.zip");
+        assert_eq!(files[0].size, Some(4096));
+        assert_eq!(files[0].modified.as_deref(), Some("2020-01-01 00:00"));
+    }
+
+    #[tokio::test]
+    async fn test_list_local_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let dir = root.join("1/2/3/1234");
+        fs::create_dir_all(dir.join("1234-h")).unwrap();
+        fs::write(dir.join("1234.txt"), "text").unwrap();
+        fs::write(dir.join("1234.zip"), "zip").unwrap();
+
+        let entries = list_local_dir(&dir).await.unwrap();
+        let hrefs: Vec<&str> = entries.iter().map(|e| e.href.as_str()).collect();
+        assert_eq!(hrefs, vec!["1234-h/", "1234.txt", "1234.zip"]);
+        assert_eq!(entries[2].size, Some(3));
+        assert!(entries[2].modified.is_some());
+        assert!(list_local_dir(&root.join("missing"))
+            .await
+            .unwrap()
+            .is_empty());
+
+        let base_url = format!("file://{}/", root.display());
+        let current = format!("{}1/2/3/1234/", base_url);
+        let (extracted, files) = extract_entries(
+            &base_url,
+            &current,
+            entries,
+            &FormatPolicy::default(),
+            &PathExcludes::default(),
+        );
+        assert_eq!(extracted.directories, vec![format!("{}1234-h/", current)]);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "1/2/3/1234/1234.zip");
+        assert_eq!(files[0].size, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_mirror_robots() {
+        let listing = r#"<pre><a href="../">Parent Directory</a>
+<a href="1.zip">1.zip</a>     2020-01-01 00:00   4K
+<a href="2/">2/</a>           2020-01-01 00:00    -
+</pre>"#;
+        // Round robin hands 1/2/ to the mirror, which disallows it
+        let (primary, primary_requests) = serve(HashMap::from([
+            ("/1/", vec![listing.into()]),
+            ("/robots.txt", vec!["User-agent: *\nDisallow:".into()]),
+        ]))
+        .await;
+        let (mirror, mirror_requests) = serve(HashMap::from([(
+            "/robots.txt",
+            vec!["User-agent: *\nDisallow: /1/".into()],
+        )]))
+        .await;
+        let config: Config = toml::from_str(&format!(
+            r#"
+url = "{}"
+mirrors = ["{}"]
+whitelist = ["1/"]
+[crawler_settings]
+retry = 1
+"#,
+            primary, mirror
+        ))
+        .unwrap();
+        let summary = Crawler::new(&config).unwrap().crawl().await.unwrap();
+        assert_eq!(summary.files.len(), 1);
+        assert!(summary.failed.is_empty());
+        // The mirror was asked for its rules, and then left alone
+        assert_eq!(*mirror_requests.lock().unwrap(), vec!["/robots.txt"]);
+        assert!(primary_requests
+            .lock()
+            .unwrap()
+            .contains(&"/robots.txt".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_failed_directories() {
+        // Every listing fails to connect
+        let config: Config = toml::from_str(&format!(
+            r#"
+url = "{}"
+whitelist = ["1/"]
+[crawler_settings]
+retry = 1
+"#,
+            UNREACHABLE_URL
+        ))
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("crawl_state.json");
+        let crawler = Crawler::new(&config).unwrap().with_checkpoint(&state_path);
+        let summary = crawler.crawl().await.unwrap();
+        assert_eq!(summary.failed, vec!["http://127.0.0.1:1/1/"]);
+
+        // Not marked visited, so a resume lists it again
+        let state = CrawlState::load(&state_path).unwrap();
+        assert!(state.visited.is_empty());
+        assert_eq!(state.failed, summary.failed);
+        let summary = crawler.run(state).await.unwrap();
+        assert_eq!(summary.failed, vec!["http://127.0.0.1:1/1/"]);
+    }
+
+    #[tokio::test]
+    async fn test_host_throttle() {
+        let throttle = HostThrottle::new(Duration::from_millis(50));
+        let start = Instant::now();
+        throttle.wait("https://abc.0/1/").await;
+        throttle.wait("https://abc.0/2/").await;
+        // a different host is not held back
+        throttle.wait("https://def.0/1/").await;
+        throttle.wait("https://abc.0/3/").await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let start = Instant::now();
+        throttle.raise_delay("https://def.0/robots.txt", Duration::from_millis(150));
+        throttle.wait("https://def.0/2/").await;
+        throttle.wait("https://def.0/3/").await;
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+}
@@ -1,43 +1,604 @@
 use anyhow::{anyhow, Error};
-use reqwest::Url;
-use std::path::PathBuf;
-use std::time::Instant;
-use trauma::{download::Download, downloader::DownloaderBuilder};
+use futures::stream::{self, StreamExt};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::{Client, StatusCode};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 use crate::config::Config;
+use crate::crawler::host_of;
+use crate::integrity::{check_zip, quarantine};
+use crate::manifest::{FileKind, ManifestEntry};
+use crate::mirrors::MirrorPool;
+use crate::report::{DownloadRecord, DownloadStatus};
+use crate::retry::{fetch_with_retry, is_retryable_status, RetryPolicy};
+use crate::shutdown::Shutdown;
 
+// Defaults for the keys left out of [download_settings]
+const DEFAULT_RETRY: u8 = 3;
+const DEFAULT_QUARANTINE_PATH: &str = ".cache/quarantine";
+// Listing times have minute precision
+const MTIME_TOLERANCE: Duration = Duration::from_secs(60);
+
+/// What to check on a zip before it gets its real name
+#[derive(Debug, Clone)]
+struct ArchiveChecks {
+    enabled: bool,
+    crc: bool,
+    quarantine: PathBuf,
+}
+
+impl ArchiveChecks {
+    fn from_config(config: &Config) -> Self {
+        let settings = &config.download_settings;
+        ArchiveChecks {
+            enabled: settings.verify.unwrap_or(true),
+            crc: settings.verify_crc.unwrap_or(false),
+            quarantine: PathBuf::from(
+                settings
+                    .quarantine_path
+                    .as_deref()
+                    .unwrap_or(DEFAULT_QUARANTINE_PATH),
+            ),
+        }
+    }
+
+    fn applies_to(&self, entry: &ManifestEntry) -> bool {
+        self.enabled && entry.kind == FileKind::Zip
+    }
+
+    /// Size against the listing, then the archive itself
+    async fn verify(&self, path: &Path, entry: &ManifestEntry, crc: bool) -> Result<(), Error> {
+        let len = fs::metadata(path).await?.len();
+        if let Some(size) = entry.size {
+            if !size_matches(size, len) {
+                return Err(anyhow!("{} bytes, the listing says {}", len, size));
+            }
+        }
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || check_zip(&path, crc)).await?
+    }
+
+    fn quarantine(&self, path: &Path, entry: &ManifestEntry) -> Result<PathBuf, Error> {
+        quarantine(path, &self.quarantine, &entry.path)
+    }
+}
+
+/// Caps the combined rate of all transfers. Every chunk reserves airtime
+/// after the chunks before it, so concurrent downloads share the budget
+/// instead of each getting the full rate.
+struct BandwidthLimit {
+    bytes_per_sec: Option<u64>,
+    next_free: Mutex<tokio::time::Instant>,
+}
+
+impl BandwidthLimit {
+    fn new(bytes_per_sec: Option<u64>) -> Self {
+        BandwidthLimit {
+            bytes_per_sec: bytes_per_sec.filter(|&rate| rate > 0),
+            next_free: Mutex::new(tokio::time::Instant::now()),
+        }
+    }
+
+    /// Waits until `bytes` more fit under the limit
+    async fn consume(&self, bytes: u64) {
+        let Some(rate) = self.bytes_per_sec else {
+            return;
+        };
+        let airtime = Duration::from_secs_f64(bytes as f64 / rate as f64);
+        let done = {
+            let mut next_free = self.next_free.lock().unwrap();
+            // Idle time is not saved up for a later burst
+            let start = (*next_free).max(tokio::time::Instant::now());
+            *next_free = start + airtime;
+            *next_free
+        };
+        tokio::time::sleep_until(done).await;
+    }
+}
+
+/// At most `limit` open transfers per host, held for the whole file
+struct HostConnections {
+    limit: Option<usize>,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostConnections {
+    fn new(limit: Option<usize>) -> Self {
+        HostConnections {
+            limit: limit.filter(|&limit| limit > 0),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn acquire(&self, url: &str) -> Option<OwnedSemaphorePermit> {
+        let limit = self.limit?;
+        let semaphore = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host_of(url))
+            .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+            .clone();
+        // The semaphore is never closed
+        semaphore.acquire_owned().await.ok()
+    }
+}
+
+/// Everything the transfers of one run share
+struct Transfers {
+    /// Copy from this folder instead of fetching, see [`Config::local_root`]
+    local_root: Option<PathBuf>,
+    hardlink: bool,
+    client: Client,
+    retry: RetryPolicy,
+    checks: ArchiveChecks,
+    bandwidth: BandwidthLimit,
+    connections: HostConnections,
+    /// Counts bytes so indicatif can show the live rate
+    throughput: ProgressBar,
+}
+
+/// Fetches every entry below `download_path` and returns one record per
+/// entry, failures included. Files already on disk are skipped, each
+/// transfer goes to `<name>.part` first and is renamed once complete, so a
+/// file under its real name is always whole. Zips that fail verification
+/// are quarantined and tried again on the next mirror, with one extra
+/// round so this also happens when there is a single mirror.
+///
+/// Stops early when `shutdown` triggers and returns the records of the
+/// files finished so far, check [`Shutdown::is_triggered`] to tell. Transfers
+/// in flight are dropped, partial files are resumed with a Range request
+/// next run.
 pub async fn download(
-    full_url: Vec<String>,
+    entries: Vec<ManifestEntry>,
     download_path: &str,
     config: &Config,
-) -> Result<(), Error> {
+    shutdown: &Shutdown,
+) -> Result<Vec<DownloadRecord>, Error> {
     let start = Instant::now();
-    let concurrency = config.download_settings.concurrency;
-    let downloads: Vec<Download> = full_url
-        .into_iter()
-        .filter_map(|url| {
-            // Try to build the download path and handle the Result
-            match build_download_path(&url, &config.url, download_path) {
-                Ok(path) => Some(Download {
-                    url: match Url::parse(&url) {
-                        Ok(parsed_url) => parsed_url,
-                        Err(_) => return None, // Filter out invalid URLs
-                    },
-                    filename: path.to_string_lossy().into_owned(),
-                }),
-                Err(_) => None, // Filter out URLs that fail to build a download path
+    let concurrency = config.download_settings.concurrency.max(1);
+    let mirrors = MirrorPool::from_config(config);
+    let settings = &config.download_settings;
+    let mut records = Vec::new();
+    // Keep the url under the primary mirror, files land in the same place
+    // whichever mirror they are fetched from. Each file carries the mirrors
+    // it was already tried on.
+    let mut pending: Vec<(ManifestEntry, PathBuf, Vec<String>)> = Vec::new();
+    for entry in entries {
+        match build_download_path(&entry.url, &config.url, download_path) {
+            Ok(path) => pending.push((entry, path, Vec::new())),
+            Err(e) => {
+                let mut record = DownloadRecord::new(&entry.url, "");
+                record.status = DownloadStatus::Invalid;
+                record.error = Some(e.to_string());
+                records.push(record);
+            }
+        }
+    }
+
+    let bars = MultiProgress::new();
+    let pb = bars.add(ProgressBar::new(pending.len() as u64));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+        )
+        .unwrap()
+        .progress_chars("##-"),
+    );
+    let throughput = bars.add(ProgressBar::no_length());
+    throughput.set_style(
+        ProgressStyle::with_template("{binary_bytes_per_sec:>12} {bytes:>10} received").unwrap(),
+    );
+    let transfers = Transfers {
+        local_root: config.local_root(),
+        hardlink: settings.hardlink.unwrap_or(true),
+        client: Client::builder().user_agent(config.user_agent()).build()?,
+        retry: RetryPolicy::new(settings.retry.unwrap_or(DEFAULT_RETRY) as usize),
+        checks: ArchiveChecks::from_config(config),
+        bandwidth: BandwidthLimit::new(settings.max_bytes_per_sec),
+        connections: HostConnections::new(settings.max_connections_per_host),
+        throughput,
+    };
+    // Only the last attempt of a file that never succeeded is reported
+    let mut failed: Vec<DownloadRecord> = Vec::new();
+    // Urls whose last attempt ended in quarantine
+    let mut quarantined: HashSet<String> = HashSet::new();
+
+    // Each round spreads the remaining files over the healthy mirrors,
+    // files that failed are tried again on another mirror next round
+    for round in 0..=mirrors.len() {
+        if round == mirrors.len() {
+            // Every mirror had its turn, only a quarantined zip gets one
+            // more since the next copy is likely whole, even with one mirror
+            pending.retain(|(entry, _, _)| quarantined.contains(&entry.url));
+            records.extend(
+                failed
+                    .drain(..)
+                    .filter(|record| !quarantined.contains(&record.url)),
+            );
+        }
+        if pending.is_empty() {
+            break;
+        }
+        if round > 0 {
+            pb.println(format!("Retrying {} failed downloads", pending.len()));
+        }
+        failed.clear();
+        quarantined.clear();
+        let mut round_results = stream::iter(std::mem::take(&mut pending))
+            .map(|(entry, path, mut tried)| {
+                let (mirrors, transfers) = (&mirrors, &transfers);
+                async move {
+                    let started = Instant::now();
+                    let mirror = mirrors.pick_untried(&tried);
+                    tried.push(mirror.to_string());
+                    let mut record = DownloadRecord::new(&entry.url, &path.to_string_lossy());
+                    let result = match (&transfers.local_root, mirrors.rewrite(&entry.url, mirror))
+                    {
+                        (Some(root), _) => {
+                            copy_local(transfers, root, &path, &entry, &mut record).await
+                        }
+                        (None, Ok(url)) => {
+                            fetch_file(transfers, &url, &path, &entry, &mut record).await
+                        }
+                        (None, Err(e)) => Err(e),
+                    };
+                    let (mut mirror_fault, mut corrupt) = (false, false);
+                    match result {
+                        Ok(status) => record.status = status,
+                        Err(e) => {
+                            mirror_fault = blames_mirror(&e, &record);
+                            corrupt = e.is::<Quarantined>();
+                            record.error = Some(e.to_string());
+                        }
+                    }
+                    record.duration_ms = started.elapsed().as_millis() as u64;
+                    (entry, path, tried, mirror, mirror_fault, corrupt, record)
+                }
+            })
+            .buffer_unordered(concurrency);
+        // Results are handled as they come in, so an interrupt keeps the
+        // records of every file finished before it
+        loop {
+            let result = tokio::select! {
+                result = round_results.next() => result,
+                _ = shutdown.wait() => None,
+            };
+            let Some((entry, path, tried, mirror, mirror_fault, corrupt, record)) = result else {
+                break;
+            };
+            if record.status.is_failure() {
+                log::warn!(
+                    "{}: {}",
+                    entry.url,
+                    record.error.as_deref().unwrap_or_default()
+                );
+                if mirror_fault {
+                    mirrors.report_failure(mirror);
+                }
+                if corrupt {
+                    quarantined.insert(entry.url.clone());
+                }
+                pending.push((entry, path, tried));
+                failed.push(record);
+            } else {
+                mirrors.report_success(mirror);
+                pb.inc(1);
+                records.push(record);
+            }
+        }
+        if shutdown.is_triggered() {
+            break;
+        }
+    }
+    if shutdown.is_triggered() {
+        pb.abandon();
+        transfers.throughput.abandon();
+    } else {
+        pb.finish_and_clear();
+        transfers.throughput.finish_and_clear();
+    }
+    records.extend(failed);
+
+    let count = |status| records.iter().filter(|r| r.status == status).count();
+    println!(
+        "Downloaded {}, resumed {}, skipped {} already complete, {} failed, {} invalid urls",
+        count(DownloadStatus::Downloaded),
+        count(DownloadStatus::Resumed),
+        count(DownloadStatus::Skipped),
+        count(DownloadStatus::Failed),
+        count(DownloadStatus::Invalid)
+    );
+    let received: u64 = records.iter().map(|r| r.bytes).sum();
+    let elapsed = start.elapsed();
+    println!(
+        "Download time: {:?}, {} at {}/s",
+        elapsed,
+        HumanBytes(received),
+        HumanBytes((received as f64 / elapsed.as_secs_f64().max(0.001)) as u64)
+    );
+    Ok(records)
+}
+
+// Like crawl_directory, only transport errors and 429/5xx count against a
+// mirror. A 404, a corrupt archive or a local disk error is not its fault.
+fn blames_mirror(error: &Error, record: &DownloadRecord) -> bool {
+    let bad_status = record
+        .http_status
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .is_some_and(is_retryable_status);
+    bad_status || error.downcast_ref::<reqwest::Error>().is_some()
+}
+
+/// Fetches `url` into `dest`, continuing a leftover `.part` file when the
+/// server honours the Range request. The HTTP status and the bytes
+/// received are noted in `record` as they come in.
+async fn fetch_file(
+    transfers: &Transfers,
+    url: &str,
+    dest: &Path,
+    entry: &ManifestEntry,
+    record: &mut DownloadRecord,
+) -> Result<DownloadStatus, Error> {
+    let checks = &transfers.checks;
+    if is_complete(dest, entry).await {
+        if !checks.applies_to(entry) {
+            return Ok(DownloadStatus::Skipped);
+        }
+        // Catches archives saved before verification was turned on,
+        // only the cheap directory check so reruns stay fast
+        match checks.verify(dest, entry, false).await {
+            Ok(()) => return Ok(DownloadStatus::Skipped),
+            Err(e) => {
+                let moved = checks.quarantine(dest, entry)?;
+                log::warn!("{}: {}, moved to {}", dest.display(), e, moved.display());
+            }
+        }
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let part = part_path(dest);
+    let validator_file = validator_path(dest);
+    // A leftover is only continued with the validator of the response it
+    // came from, so a file that changed on the mirror is fetched whole
+    // instead of appended to the old version
+    let validator = fs::read_to_string(&validator_file).await.ok();
+    let offset = match validator {
+        Some(_) => fs::metadata(&part).await.map(|m| m.len()).unwrap_or(0),
+        None => 0,
+    };
+
+    // Held until the body is read, the connection stays busy until then
+    let _permit = transfers.connections.acquire(url).await;
+    let mut res = fetch_with_retry(&transfers.retry, url, || {
+        let mut request = transfers.client.get(url);
+        if let (true, Some(validator)) = (offset > 0, &validator) {
+            request = request
+                .header(RANGE, format!("bytes={}-", offset))
+                .header(IF_RANGE, validator.as_str());
+        }
+        request.send()
+    })
+    .await
+    .inspect_err(|e| record.http_status = e.status().map(|status| status.as_u16()))?;
+    record.http_status = Some(res.status().as_u16());
+    let (mut file, status) = match res.status() {
+        StatusCode::PARTIAL_CONTENT if offset > 0 => (
+            fs::OpenOptions::new().append(true).open(&part).await?,
+            DownloadStatus::Resumed,
+        ),
+        // The leftover already holds every byte the server has
+        StatusCode::RANGE_NOT_SATISFIABLE
+            if entry.size.is_none_or(|size| size_matches(size, offset)) =>
+        {
+            let _ = fs::remove_file(&validator_file).await;
+            finish(&part, dest, entry, checks).await?;
+            return Ok(DownloadStatus::Resumed);
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            // The remote file shrank, start over next time
+            fs::remove_file(&part).await?;
+            let _ = fs::remove_file(&validator_file).await;
+            return Err(anyhow!("Partial file is larger than the remote file"));
+        }
+        // A plain 200 means the server ignored the range or the file
+        // changed since the leftover was written, start from scratch
+        status if status.is_success() => {
+            match range_validator(res.headers()) {
+                Some(validator) => fs::write(&validator_file, validator).await?,
+                None => {
+                    let _ = fs::remove_file(&validator_file).await;
+                }
+            }
+            (fs::File::create(&part).await?, DownloadStatus::Downloaded)
+        }
+        status => return Err(anyhow!("Server returned {}", status)),
+    };
+    while let Some(chunk) = res.chunk().await? {
+        transfers.bandwidth.consume(chunk.len() as u64).await;
+        file.write_all(&chunk).await?;
+        record.bytes += chunk.len() as u64;
+        transfers.throughput.inc(chunk.len() as u64);
+    }
+    file.flush().await?;
+    drop(file);
+    let _ = fs::remove_file(&validator_file).await;
+    finish(&part, dest, entry, checks).await?;
+    Ok(status)
+}
+
+// What `If-Range` can compare against, a strong ETag or else Last-Modified.
+// Weak ETags are not allowed there.
+fn range_validator(headers: &HeaderMap) -> Option<String> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+    };
+    header(ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(LAST_MODIFIED))
+        .map(str::to_string)
+}
+
+/// Takes the file from a local mirror, a hardlink when allowed and
+/// possible, else a copy that goes through `.part` like a download
+async fn copy_local(
+    transfers: &Transfers,
+    root: &Path,
+    dest: &Path,
+    entry: &ManifestEntry,
+    record: &mut DownloadRecord,
+) -> Result<DownloadStatus, Error> {
+    if is_complete(dest, entry).await {
+        return Ok(DownloadStatus::Skipped);
+    }
+    let source = root.join(&entry.path);
+    let len = fs::metadata(&source)
+        .await
+        .map_err(|e| anyhow!("{}: {}", source.display(), e))?
+        .len();
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    // A stale copy would make hard_link fail
+    let _ = fs::remove_file(dest).await;
+    let checks = &transfers.checks;
+    if transfers.hardlink {
+        if checks.applies_to(entry) {
+            // A link is the mirror's own file, report it but leave it be
+            checks
+                .verify(&source, entry, checks.crc)
+                .await
+                .map_err(|e| anyhow!("Corrupt archive in local mirror: {}", e))?;
+        }
+        // The link shares the source's mtime, which the listing came from
+        if fs::hard_link(&source, dest).await.is_ok() {
+            record.bytes = len;
+            return Ok(DownloadStatus::Downloaded);
+        }
+    }
+    let part = part_path(dest);
+    fs::copy(&source, &part).await?;
+    record.bytes = len;
+    transfers.throughput.inc(len);
+    finish(&part, dest, entry, checks).await?;
+    Ok(DownloadStatus::Downloaded)
+}
+
+/// A zip that arrived but failed verification, the next attempt starts over
+#[derive(Debug)]
+struct Quarantined {
+    reason: String,
+    moved: PathBuf,
+}
+
+impl fmt::Display for Quarantined {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Corrupt archive ({}), moved to {}",
+            self.reason,
+            self.moved.display()
+        )
+    }
+}
+
+impl std::error::Error for Quarantined {}
+
+// Moves the finished `.part` into place and stamps it with the listing
+// time. A corrupt archive goes to quarantine instead, so the next attempt
+// starts from scratch.
+async fn finish(
+    part: &Path,
+    dest: &Path,
+    entry: &ManifestEntry,
+    checks: &ArchiveChecks,
+) -> Result<(), Error> {
+    if checks.applies_to(entry) {
+        if let Err(e) = checks.verify(part, entry, checks.crc).await {
+            let moved = checks.quarantine(part, entry)?;
+            return Err(Quarantined {
+                reason: e.to_string(),
+                moved,
             }
-        })
-        .collect();
-    let downloader = DownloaderBuilder::new()
-        .concurrent_downloads(concurrency)
-        .build();
-    downloader.download(&downloads).await;
-
-    println!("Download time: {:?}", start.elapsed());
+            .into());
+        }
+    }
+    fs::rename(part, dest).await?;
+    if let Some(modified) = entry.modified_time() {
+        let file = std::fs::File::options().write(true).open(dest)?;
+        file.set_modified(modified)?;
+    }
     Ok(())
 }
 
+/// `dest` with ".part" appended, "1234-0.zip" -> "1234-0.zip.part"
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = OsString::from(dest.as_os_str());
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+// Next to the `.part`, the ETag or Last-Modified it was downloaded with
+fn validator_path(dest: &Path) -> PathBuf {
+    let mut name = OsString::from(part_path(dest).as_os_str());
+    name.push(".validator");
+    PathBuf::from(name)
+}
+
+// Files only get their real name once complete, so beyond existing they
+// need to agree with the manifest to count as up to date
+async fn is_complete(dest: &Path, entry: &ManifestEntry) -> bool {
+    let Ok(meta) = fs::metadata(dest).await else {
+        return false;
+    };
+    if let Some(size) = entry.size {
+        if !size_matches(size, meta.len()) {
+            return false;
+        }
+    }
+    match (entry.modified_time(), meta.modified()) {
+        (Some(expected), Ok(actual)) => {
+            let gap = match actual.duration_since(expected) {
+                Ok(gap) => gap,
+                Err(e) => e.duration(),
+            };
+            gap <= MTIME_TOLERANCE
+        }
+        _ => true,
+    }
+}
+
+// Listings round sizes to "152K" or "1.2M", so anything from 1K up only
+// has to be within 5%
+fn size_matches(listed: u64, actual: u64) -> bool {
+    if listed < 1024 {
+        return listed == actual;
+    }
+    listed.abs_diff(actual) * 20 <= listed
+}
+
+/// The part of `url` after `website_url`, shared by the download path
+/// and the mirror rewriting so both agree on what a url points at.
+/// Never starts with a slash, a local root is often written without one.
+pub(crate) fn url_suffix<'a>(url: &'a str, website_url: &str) -> Result<&'a str, Error> {
+    match url.strip_prefix(website_url) {
+        Some(suffix) => Ok(suffix.trim_start_matches('/')),
+        None => Err(anyhow!("The url didn't have the right prefix?")),
+    }
+}
+
 ///
 /// Role of this function is to build the folder for the
 /// download, not the file directly
@@ -47,10 +608,7 @@ fn build_download_path(
     website_url: &str,
     download_folder: &str,
 ) -> Result<PathBuf, Error> {
-    let suffix = match url.strip_prefix(website_url) {
-        Some(suffix) => suffix,
-        None => return Err(anyhow!("The url didn't have the right prefix?")),
-    };
+    let suffix = url_suffix(url, website_url)?;
 
     // Create the path
     let mut download_file = PathBuf::from(download_folder);
@@ -65,6 +623,7 @@ mod tests {
     use std::{fs, io::Write};
 
     use super::*;
+    use crate::test_server::{serve, UNREACHABLE_URL};
 
     #[test]
     fn test_build_download_path() {
@@ -80,12 +639,230 @@ mod tests {
         let clean_name = build_download_path(url, website_url, download_folder).unwrap();
         assert_eq!(clean_name, PathBuf::from("download/1.html"));
 
+        // a local root without a trailing slash stays under the download folder
+        let clean_name =
+            build_download_path("/srv/gutenberg/0/4/4.zip", "/srv/gutenberg", "download").unwrap();
+        assert_eq!(clean_name, PathBuf::from("download/0/4/4.zip"));
+        let clean_name = build_download_path(
+            "file:///srv/gutenberg/0/4/4.zip",
+            "file:///srv/gutenberg",
+            "download",
+        )
+        .unwrap();
+        assert_eq!(clean_name, PathBuf::from("download/0/4/4.zip"));
+
         // wrong suffix
         let url = "https://abc.1/1.html";
         let download_folder = "download";
         let clean_name = build_download_path(url, website_url, download_folder);
         assert!(clean_name.is_err());
     }
+
+    #[tokio::test]
+    async fn test_bandwidth_limit() {
+        let unlimited = BandwidthLimit::new(None);
+        let start = Instant::now();
+        unlimited.consume(1 << 30).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // 4 chunks of 100 bytes at 1000 bytes/s take at least 0.4s together
+        let limit = BandwidthLimit::new(Some(1000));
+        let start = Instant::now();
+        futures::future::join_all((0..4).map(|_| limit.consume(100))).await;
+        assert!(start.elapsed() >= Duration::from_millis(390));
+    }
+
+    #[tokio::test]
+    async fn test_host_connections() {
+        let connections = HostConnections::new(Some(1));
+        let first = connections
+            .acquire("https://gutenberg.pglaf.org/1.zip")
+            .await;
+        assert!(first.is_some());
+        // Another host has its own budget
+        assert!(connections
+            .acquire("https://aleph.gutenberg.org/1.zip")
+            .await
+            .is_some());
+        let second = tokio::time::timeout(
+            Duration::from_millis(50),
+            connections.acquire("https://gutenberg.pglaf.org/2.zip"),
+        )
+        .await;
+        assert!(second.is_err());
+        drop(first);
+        assert!(connections
+            .acquire("https://gutenberg.pglaf.org/2.zip")
+            .await
+            .is_some());
+        assert!(HostConnections::new(None)
+            .acquire("https://gutenberg.pglaf.org/")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_blames_mirror() {
+        let mut record = DownloadRecord::new("https://gutenberg.pglaf.org/0/4/4.zip", "0/4/4.zip");
+        let refused = reqwest::get(UNREACHABLE_URL).await.unwrap_err();
+        assert!(blames_mirror(&refused.into(), &record));
+
+        let error = anyhow!("Server returned 404 Not Found");
+        record.http_status = Some(404);
+        assert!(!blames_mirror(&error, &record));
+        record.http_status = Some(503);
+        assert!(blames_mirror(&error, &record));
+        // Downloaded fine but failed verification
+        record.http_status = Some(200);
+        assert!(!blames_mirror(&anyhow!("Corrupt archive"), &record));
+        record.http_status = None;
+        let io = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "read-only");
+        assert!(!blames_mirror(&io.into(), &record));
+    }
+
+    #[test]
+    fn test_part_path() {
+        assert_eq!(
+            part_path(Path::new("download/1/2/3/1234/1234-0.zip")),
+            PathBuf::from("download/1/2/3/1234/1234-0.zip.part")
+        );
+    }
+
+    #[test]
+    fn test_range_validator() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(range_validator(&headers), None);
+        headers.insert(
+            LAST_MODIFIED,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        headers.insert(ETAG, HeaderValue::from_static("W/\"weak\""));
+        assert_eq!(
+            range_validator(&headers).as_deref(),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+        headers.insert(ETAG, HeaderValue::from_static("\"5f-5a1\""));
+        assert_eq!(range_validator(&headers).as_deref(), Some("\"5f-5a1\""));
+        assert_eq!(
+            validator_path(Path::new("download/1/1.zip")),
+            PathBuf::from("download/1/1.zip.part.validator")
+        );
+    }
+
+    #[test]
+    fn test_size_matches() {
+        assert!(size_matches(512, 512));
+        assert!(!size_matches(512, 511));
+        // "152K"
+        assert!(size_matches(152 * 1024, 152 * 1024 + 300));
+        assert!(!size_matches(152 * 1024, 100 * 1024));
+    }
+
+    #[tokio::test]
+    async fn test_is_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("1234.txt");
+        let mut entry = ManifestEntry::new(
+            "https://abc.0/",
+            "https://abc.0/1/2/3/1234/1234.txt",
+            Some(5),
+            Some("2019-03-02 05:42".to_string()),
+        );
+        assert!(!is_complete(&dest, &entry).await);
+
+        std::fs::write(&dest, "hello").unwrap();
+        // Fresh mtime does not match the listing
+        assert!(!is_complete(&dest, &entry).await);
+        let file = std::fs::File::options().write(true).open(&dest).unwrap();
+        file.set_modified(entry.modified_time().unwrap()).unwrap();
+        assert!(is_complete(&dest, &entry).await);
+
+        entry.size = Some(6);
+        assert!(!is_complete(&dest, &entry).await);
+    }
+    #[tokio::test]
+    async fn test_download_interrupted() {
+        let (url, requests) = serve(HashMap::from([
+            ("/1/1.txt", vec![b"hello".to_vec()]),
+            ("/2/2.txt", vec![]),
+        ]))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            url: url.clone(),
+            ..Default::default()
+        };
+        // One at a time, 2.txt is only asked for once 1.txt is recorded
+        config.download_settings.concurrency = 1;
+        let entries = vec![
+            ManifestEntry::new(&url, &format!("{}1/1.txt", url), None, None),
+            ManifestEntry::new(&url, &format!("{}2/2.txt", url), None, None),
+        ];
+        // 2.txt never gets an answer, Ctrl-C while waiting for it
+        let shutdown = Shutdown::new();
+        let trigger = shutdown.clone();
+        tokio::spawn(async move {
+            while !requests.lock().unwrap().contains(&"/2/2.txt".to_string()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            trigger.trigger();
+        });
+
+        let download_path = dir.path().to_string_lossy();
+        let records = download(entries, &download_path, &config, &shutdown)
+            .await
+            .unwrap();
+        assert!(shutdown.is_triggered());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].url, format!("{}1/1.txt", url));
+        assert_eq!(records[0].status, DownloadStatus::Downloaded);
+        assert_eq!(fs::read(dir.path().join("1/1.txt")).unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_one_mirror() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("1.txt", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"The Project Gutenberg eBook").unwrap();
+        let whole = zip.finish().unwrap().into_inner();
+        // The first copy is an error page, the second one is fine
+        let (url, requests) = serve(HashMap::from([(
+            "/1/1.zip",
+            vec![b"<html>503</html>".to_vec(), whole.clone()],
+        )]))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            url: url.clone(),
+            ..Default::default()
+        };
+        let quarantine_dir = dir.path().join("quarantine");
+        config.download_settings.quarantine_path =
+            Some(quarantine_dir.to_string_lossy().to_string());
+        let entries = vec![ManifestEntry::new(
+            &url,
+            &format!("{}1/1.zip", url),
+            None,
+            None,
+        )];
+
+        let download_path = dir.path().join("download");
+        let records = download(
+            entries,
+            &download_path.to_string_lossy(),
+            &config,
+            &Shutdown::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status, DownloadStatus::Downloaded);
+        assert_eq!(fs::read(download_path.join("1/1.zip")).unwrap(), whole);
+        assert!(quarantine_dir.join("1/1.zip").exists());
+    }
+
     #[tokio::test]
     async fn test_raw_download() {
         // this is just a functional check on pure reqwest and the url
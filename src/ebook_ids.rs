@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Error};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Where an ebook lives on the mirror. Every digit but the last becomes a
+/// folder, then the full number: 1234 -> "1/2/3/1234/". Single digit
+/// ebooks live under "0/".
+pub fn ebook_dir(id: u32) -> String {
+    let digits = id.to_string();
+    if digits.len() == 1 {
+        return format!("0/{}/", digits);
+    }
+    let mut path = String::new();
+    for digit in digits[..digits.len() - 1].chars() {
+        path.push(digit);
+        path.push('/');
+    }
+    format!("{}{}/", path, digits)
+}
+
+// Ranges are expanded in memory, the collection is still far below this
+const MAX_EBOOK_ID: u32 = 9_999_999;
+
+/// Parses "1000-2000,42 7" into a sorted list of unique ids
+pub fn parse_id_ranges(spec: &str) -> Result<Vec<u32>, Error> {
+    // "1000 - 2000" is one range, not three parts
+    let spec = spec.split('-').map(str::trim).collect::<Vec<_>>().join("-");
+    let mut ids = BTreeSet::new();
+    for part in spec.split([',', ' ', '\n', '\t']).map(str::trim) {
+        if part.is_empty() {
+            continue;
+        }
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let start: u32 = start
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid id range {:?}", part))?;
+                let end: u32 = end
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid id range {:?}", part))?;
+                if start > end {
+                    return Err(anyhow!("Invalid id range {:?}, start is after end", part));
+                }
+                if end > MAX_EBOOK_ID {
+                    return Err(anyhow!(
+                        "Invalid id range {:?}, ids go up to {}",
+                        part,
+                        MAX_EBOOK_ID
+                    ));
+                }
+                ids.extend(start..=end);
+            }
+            None => {
+                ids.insert(
+                    part.parse()
+                        .map_err(|_| anyhow!("Invalid ebook id {:?}", part))?,
+                );
+            }
+        }
+    }
+    Ok(ids.into_iter().collect())
+}
+
+/// Reads ids from a file. Accepts the JSON written by the `index` binary
+/// (an object keyed by ebook id), a JSON array of ids, or plain text with
+/// ids and ranges separated by commas or newlines.
+pub fn read_id_file(path: impl AsRef<Path>) -> Result<Vec<u32>, Error> {
+    let text = fs::read_to_string(path)?;
+    let trimmed = text.trim_start();
+    if !(trimmed.starts_with('{') || trimmed.starts_with('[')) {
+        return parse_id_ranges(&text);
+    }
+    let value: serde_json::Value = serde_json::from_str(&text)?;
+    let mut ids = BTreeSet::new();
+    match value {
+        serde_json::Value::Object(map) => {
+            for key in map.keys() {
+                ids.insert(
+                    key.parse()
+                        .map_err(|_| anyhow!("Invalid ebook id {:?}", key))?,
+                );
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                let id = item
+                    .as_u64()
+                    .and_then(|id| u32::try_from(id).ok())
+                    .ok_or_else(|| anyhow!("Invalid ebook id {}", item))?;
+                ids.insert(id);
+            }
+        }
+        _ => return Err(anyhow!("Expected a JSON object or array of ebook ids")),
+    }
+    Ok(ids.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ebook_dir() {
+        assert_eq!(ebook_dir(5), "0/5/");
+        assert_eq!(ebook_dir(12), "1/12/");
+        assert_eq!(ebook_dir(123), "1/2/123/");
+        assert_eq!(ebook_dir(1234), "1/2/3/1234/");
+        assert_eq!(ebook_dir(70654), "7/0/6/5/70654/");
+    }
+
+    #[test]
+    fn test_parse_id_ranges() {
+        assert_eq!(
+            parse_id_ranges("10-12, 42\n11 7").unwrap(),
+            vec![7, 10, 11, 12, 42]
+        );
+        assert_eq!(
+            parse_id_ranges("1000 - 1002,5 -6").unwrap(),
+            vec![5, 6, 1000, 1001, 1002]
+        );
+        assert!(parse_id_ranges("12-10").is_err());
+        assert!(parse_id_ranges("1-4000000000").is_err());
+        assert!(parse_id_ranges("abc").is_err());
+    }
+
+    #[test]
+    fn test_read_id_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let index = dir.join("index.json");
+        fs::write(
+            &index,
+            r#"{"70654": "Submerged forests", "70653": "Rattle of bones"}"#,
+        )
+        .unwrap();
+        assert_eq!(read_id_file(&index).unwrap(), vec![70653, 70654]);
+        let list = dir.join("ids.txt");
+        fs::write(&list, "1\n5-6\n").unwrap();
+        assert_eq!(read_id_file(&list).unwrap(), vec![1, 5, 6]);
+    }
+}
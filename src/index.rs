@@ -1,77 +1,290 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
 
-static TITLE_STRING: &str = "TITLE and AUTHOR";
+// Compared in upper case, the 1990s files write "Title and Author"
+static TITLE_STRING: &str = "TITLE AND AUTHOR";
 static END_STRING: &str = "<==End of ";
+static MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
 
+/// A line inside a listing that is neither an entry nor part of one
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct UnparsedLine {
+    /// Counted from 1
+    pub line: usize,
+    pub text: String,
+}
+
+/// An ebook number listed again in the same file, the first listing is kept
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DuplicateEntry {
+    pub id: u32,
+    /// Where the second listing starts, counted from 1
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedIndex {
+    /// The title line and its continuation lines joined with `\n`
+    pub entries: HashMap<u32, String>,
+    pub unparsed: Vec<UnparsedLine>,
+    pub duplicates: Vec<DuplicateEntry>,
+}
+
+impl ParsedIndex {
+    pub fn into_entries(self) -> BTreeMap<u32, GutIndexEntry> {
+        self.entries
+            .into_iter()
+            .map(|(id, raw)| (id, GutIndexEntry::parse(id, &raw)))
+            .collect()
+    }
+
+    fn finish(&mut self, entry: Option<(usize, u32, String)>) {
+        let Some((line, id, text)) = entry else {
+            return;
+        };
+        match self.entries.entry(id) {
+            Entry::Vacant(slot) => {
+                slot.insert(text);
+            }
+            Entry::Occupied(_) => self.duplicates.push(DuplicateEntry { id, line, text }),
+        }
+    }
+}
+
+/// Map of ebook number to its lines in the index, see [`parse_index`]
 pub fn index(text: &str) -> HashMap<u32, String> {
-    let mut return_value: HashMap<u32, String> = HashMap::new();
-    let mut start = false;
-    // this bool is to help when the line is longer than 1 line
-    let mut in_title = false;
-
-    // initialize some start values
-    let mut index = 0;
-    let mut title = String::new();
-    for line in text.lines() {
-        if line.contains(TITLE_STRING) {
-            start = true;
+    parse_index(text).entries
+}
+
+/// Reads every listing in a GUTINDEX file. A listing starts at a
+/// `TITLE and AUTHOR` header and runs to the next header or `<==End of`,
+/// so repeated headers after page breaks and later sections are all read.
+/// Indented and `[Tag: ...]` lines continue the entry above them, any other
+/// line that is not an entry ends up in `unparsed`.
+pub fn parse_index(text: &str) -> ParsedIndex {
+    let mut parsed = ParsedIndex::default();
+    let mut in_listing = false;
+    // The entry whose continuation lines may still follow, with its line
+    let mut current: Option<(usize, u32, String)> = None;
+    for (number, line) in text.lines().enumerate() {
+        // Page breaks are form feeds, on their own or before a header
+        if line.contains('\x0c') && line.trim_matches(['\x0c', ' ', '\t']).is_empty() {
             continue;
         }
-        if line.starts_with(END_STRING) {
-            break;
+        let line = line.trim_matches('\x0c').trim_end();
+        if line.to_uppercase().contains(TITLE_STRING) {
+            parsed.finish(current.take());
+            in_listing = true;
+            continue;
         }
-        if start {
-            // we already started parsing this
-            if !in_title {
-                let parsed = parse_line(line);
-                index = parsed.0;
-                title = parsed.1;
-                in_title = true;
-                continue;
-            } else {
-                // When we already found a title, we need to keep adding to it
-                if line.is_empty() {
-                    // This is the end of the title
-                    in_title = false;
-                    return_value.insert(index, title.clone());
-                    // Reset title for next entry
-                    title = String::new();
-                    continue;
-                }
-                // Add the line to the title
-                let line = line.trim();
+        if line.trim_start().starts_with(END_STRING) {
+            parsed.finish(current.take());
+            in_listing = false;
+            continue;
+        }
+        if !in_listing {
+            continue;
+        }
+        if line.is_empty() || is_separator(line) {
+            parsed.finish(current.take());
+            continue;
+        }
+        // Continuation lines are indented, e.g. " [Language: Finnish]",
+        // a few files leave the tags unindented
+        let continuation = line.starts_with(char::is_whitespace) || line.starts_with('[');
+        match &mut current {
+            Some((_, _, title)) if continuation => {
                 title.push('\n');
-                title.push_str(line);
+                title.push_str(line.trim());
+            }
+            _ => match parse_line(line) {
+                Some((id, title)) => parsed.finish(current.replace((number + 1, id, title))),
+                None => parsed.unparsed.push(UnparsedLine {
+                    line: number + 1,
+                    text: line.to_string(),
+                }),
+            },
+        }
+    }
+    parsed.finish(current);
+    parsed
+}
+
+// "~ ~ ~ ~ Posting Dates for the below eBooks: ..." and rules of = or -
+fn is_separator(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with('~') || line.chars().all(|c| "=-~*_ ".contains(c))
+}
+
+/// One ebook of the index, with the `, by ` author and the bracketed
+/// tags split out of the text [`index`] collects
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct GutIndexEntry {
+    pub id: u32,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub illustrator: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contents: Option<String>,
+    /// Any other tag, e.g. Editor or Translator
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub other: BTreeMap<String, String>,
+    /// The lines as they appear in the index
+    pub raw: String,
+}
+
+impl GutIndexEntry {
+    pub fn parse(id: u32, raw: &str) -> Self {
+        let mut entry = GutIndexEntry {
+            id,
+            raw: raw.to_string(),
+            ..Default::default()
+        };
+        // Long titles wrap onto the next lines before the tags start, a
+        // tag can wrap too until its closing bracket
+        let mut heading = String::new();
+        let mut tags: Vec<String> = Vec::new();
+        for line in raw.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            match tags.last_mut() {
+                Some(tag) if tag.matches('[').count() > tag.matches(']').count() => {
+                    tag.push(' ');
+                    tag.push_str(line);
+                }
+                _ if line.starts_with('[') => tags.push(line.to_string()),
+                _ => {
+                    if !heading.is_empty() {
+                        heading.push(' ');
+                    }
+                    heading.push_str(line);
+                }
+            }
+        }
+        match heading.rsplit_once(", by ") {
+            Some((title, author)) => {
+                entry.title = title.trim().to_string();
+                entry.author = Some(author.trim().to_string());
             }
+            None => entry.title = heading,
         }
+        for tag in tags {
+            let inner = tag.trim_start_matches('[').trim_end_matches(']');
+            let (key, value) = inner.split_once(':').unwrap_or((inner, ""));
+            let value = value.trim().to_string();
+            match key.trim().to_lowercase().as_str() {
+                "subtitle" => entry.subtitle = Some(value),
+                "language" | "languages" => entry.language = Some(value),
+                "illustrator" | "illustrators" => entry.illustrator = Some(value),
+                "contents" => entry.contents = Some(value),
+                _ => {
+                    entry.other.insert(key.trim().to_string(), value);
+                }
+            }
+        }
+        entry
     }
-    // check if we have a title that was not added
-    if !title.is_empty() {
-        return_value.insert(index, title);
+
+    /// Title and author the way the index writes them
+    pub fn heading(&self) -> String {
+        match &self.author {
+            Some(author) => format!("{}, by {}", self.title, author),
+            None => self.title.clone(),
+        }
     }
-    return_value
 }
 
-// This should only handle one line at a time
-fn parse_line(line: &str) -> (u32, String) {
-    // Find the last sequence of digits in the line
-    let mut number_start = line.len();
-    for (i, c) in line.char_indices().rev() {
-        if c.is_ascii_digit() {
-            number_start = i;
-        } else if number_start < line.len() {
-            break;
+/// The same ebook number with a different title or author in two files
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IndexConflict {
+    pub id: u32,
+    pub kept: String,
+    pub kept_source: String,
+    pub dropped: String,
+    pub dropped_source: String,
+}
+
+/// Entries of several index files, e.g. the yearly GUTINDEX.1996 to
+/// GUTINDEX.2024 and GUTINDEX.ALL. Files added later win.
+#[derive(Debug, Default)]
+pub struct MergedIndex {
+    pub entries: BTreeMap<u32, GutIndexEntry>,
+    pub conflicts: Vec<IndexConflict>,
+    sources: HashMap<u32, String>,
+}
+
+impl MergedIndex {
+    pub fn add(&mut self, source: &str, entries: BTreeMap<u32, GutIndexEntry>) {
+        for (id, entry) in entries {
+            if let Some(previous) = self.entries.get(&id) {
+                if normalize(&previous.heading()) != normalize(&entry.heading()) {
+                    self.conflicts.push(IndexConflict {
+                        id,
+                        kept: entry.heading(),
+                        kept_source: source.to_string(),
+                        dropped: previous.heading(),
+                        dropped_source: self.sources[&id].clone(),
+                    });
+                }
+            }
+            self.sources.insert(id, source.to_string());
+            self.entries.insert(id, entry);
         }
     }
+}
 
-    // Extract the number part
-    let number_str = &line[number_start..];
-    let number = number_str.trim().parse::<u32>().unwrap_or(0);
+// Rewrapped lines and changed capitalisation are not conflicts
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Like [`index`], with each entry parsed into a [`GutIndexEntry`]
+pub fn index_entries(text: &str) -> BTreeMap<u32, GutIndexEntry> {
+    parse_index(text).into_entries()
+}
 
-    // Extract the title part (trim trailing spaces)
-    let title = line[..number_start].trim_end().to_string();
+// This should only handle one line at a time. The ebook number is the last
+// word, older files add a letter to it (12345C), a "Mon YYYY" posting date
+// before the title and a [filename] after it.
+fn parse_line(line: &str) -> Option<(u32, String)> {
+    let line = line.trim();
+    let (title, number) = line.rsplit_once(char::is_whitespace)?;
+    let digits = number.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let number = digits.parse::<u32>().ok().filter(|&number| number > 0)?;
 
-    (number, title)
+    let mut title = title.trim();
+    if let Some((month, rest)) = title.split_once(' ') {
+        if let Some((year, rest)) = rest.split_once(' ') {
+            if MONTHS.contains(&month) && year.len() == 4 && year.parse::<u16>().is_ok() {
+                title = rest.trim_start();
+            }
+        }
+    }
+    if title.ends_with(']') {
+        if let Some(open) = title.rfind('[') {
+            if !title[open..].contains(char::is_whitespace) {
+                title = title[..open].trim_end();
+            }
+        }
+    }
+    if title.is_empty() {
+        return None;
+    }
+    Some((number, title.to_string()))
 }
 
 #[cfg(test)]
@@ -81,12 +294,12 @@ mod tests {
     fn test_clean() {
         let input_string =
             "Memoirs of Arsène Lupin, by Maurice Le Blanc                             70719";
-        let (index, title) = parse_line(input_string);
+        let (index, title) = parse_line(input_string).unwrap();
         assert_eq!(index, 70719);
         assert_eq!(title, "Memoirs of Arsène Lupin, by Maurice Le Blanc");
         let input_string =
             "The inter ocean curiosity shop for the year 1883, by Various             70718";
-        let (index, title) = parse_line(input_string);
+        let (index, title) = parse_line(input_string).unwrap();
         assert_eq!(index, 70718);
         assert_eq!(
             title,
@@ -115,4 +328,187 @@ Rattle of bones, by Robert E. Howard                                     70653
 [Illustrator: Doak]"#
         );
     }
+
+    #[test]
+    fn test_index_entry() {
+        let entry = GutIndexEntry::parse(
+            70653,
+            "Rattle of bones, by Robert E. Howard\n[Illustrator: Doak]",
+        );
+        assert_eq!(entry.title, "Rattle of bones");
+        assert_eq!(entry.author.as_deref(), Some("Robert E. Howard"));
+        assert_eq!(entry.illustrator.as_deref(), Some("Doak"));
+        assert_eq!(entry.language, None);
+
+        let input_string = r#"
+TITLE and AUTHOR
+Scientific American Supplement, No. 601, July 9, 1887,                  15011
+ by Various
+ [Subtitle: A Topical Study of the Bible, Systematically Arranged
+  for Home and Private Study]
+ [Language: Italian]
+ [Editor: Ernest Hartley Coleridge]
+ [Contents: Introduction -- Chapter I]
+
+Poems                                                                   15012
+"#;
+        let entries = index_entries(input_string);
+        let entry = &entries[&15011];
+        assert_eq!(
+            entry.title,
+            "Scientific American Supplement, No. 601, July 9, 1887"
+        );
+        assert_eq!(entry.author.as_deref(), Some("Various"));
+        assert_eq!(
+            entry.subtitle.as_deref(),
+            Some(
+                "A Topical Study of the Bible, Systematically Arranged for Home and Private Study"
+            )
+        );
+        assert_eq!(entry.language.as_deref(), Some("Italian"));
+        assert_eq!(entry.contents.as_deref(), Some("Introduction -- Chapter I"));
+        assert_eq!(entry.other["Editor"], "Ernest Hartley Coleridge");
+        assert!(entry.raw.starts_with("Scientific American Supplement"));
+        assert_eq!(entries[&15012].title, "Poems");
+        assert_eq!(entries[&15012].author, None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let year = index_entries(
+            "TITLE and AUTHOR\nPoems, by Anonymous      15012\n\nThe Raven, by Poe     17192\n",
+        );
+        let all = index_entries(
+            "TITLE and AUTHOR\nPOEMS,  by anonymous     15012\n\nThe Bells, by Poe     17192\n\nUlysses, by James Joyce    4300\n",
+        );
+        let mut merged = MergedIndex::default();
+        merged.add("GUTINDEX.2005", year);
+        merged.add("GUTINDEX.ALL", all);
+        assert_eq!(merged.entries.len(), 3);
+        assert_eq!(merged.entries[&17192].title, "The Bells");
+        assert_eq!(
+            merged.conflicts,
+            vec![IndexConflict {
+                id: 17192,
+                kept: "The Bells, by Poe".to_string(),
+                kept_source: "GUTINDEX.ALL".to_string(),
+                dropped: "The Raven, by Poe".to_string(),
+                dropped_source: "GUTINDEX.2005".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line("Dec 1999 Les Miserables, Complete, by Victor Hugo     [lmisr10x.xxx]  135"),
+            Some((135, "Les Miserables, Complete, by Victor Hugo".to_string()))
+        );
+        assert_eq!(
+            parse_line("The Mirror of Literature, by Various                    12345C   "),
+            Some((12345, "The Mirror of Literature, by Various".to_string()))
+        );
+        assert_eq!(parse_line("The Mirror of Literature, by Various"), None);
+        assert_eq!(
+            parse_line("                                           17"),
+            None
+        );
+        assert_eq!(
+            parse_line("Nothing here, by Nobody                  0"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_index_years() {
+        // Layouts of GUTINDEX.2023, GUTINDEX.2006 and GUTINDEX.1999, with a
+        // page break and repeated header in between
+        let input_string = "GUTINDEX.2023
+
+~ ~ ~ ~ Posting Dates for the below eBooks:  1 Dec 2023 to 31 Dec 2023 ~ ~ ~ ~
+
+TITLE and AUTHOR                                                     EBOOK NO.
+
+The cat in the convent, by Various                                       72471
+
+Ennen ja nyt: Kertomus, by Kaarlo Kramsu                                 72470
+ [Language: Finnish]
+\x0c
+TITLE and AUTHOR                                                     EBOOK NO.
+
+Scientific American Supplement, No. 601, July 9, 1887,                   15011
+ by Various
+
+stray text between entries
+
+<==End of GUTINDEX.2023==>
+The Index ends here, 1234
+
+TITLE and AUTHOR                                                     ETEXT NO.
+
+The Mirror of Literature, Amusement, and Instruction, by Various         20262
+ [Volume 10, No. 273, September 22, 1827]
+
+Les Misérables, by Victor Hugo                                          17489C
+ [Language: French]
+======================================================================
+
+Mon Year Title and Author                                  [filename] ETEXT #
+Dec 1999 The Bible, Douay-Rheims, Complete                  [drbbl10x.xxx] 1581
+Dec 1999 Les Miserables, Complete, by Victor Hugo           [lmisr10x.xxx]  135
+  [Translator: Isabel F. Hapgood]
+";
+        let parsed = parse_index(input_string);
+        assert_eq!(
+            parsed.unparsed,
+            vec![UnparsedLine {
+                line: 17,
+                text: "stray text between entries".to_string()
+            }]
+        );
+        let entries = parsed.into_entries();
+        let ids: Vec<u32> = entries.keys().copied().collect();
+        assert_eq!(ids, vec![135, 1581, 15011, 17489, 20262, 72470, 72471]);
+        assert_eq!(entries[&72470].language.as_deref(), Some("Finnish"));
+        assert_eq!(entries[&15011].author.as_deref(), Some("Various"));
+        assert_eq!(entries[&17489].language.as_deref(), Some("French"));
+        assert_eq!(entries[&1581].title, "The Bible, Douay-Rheims, Complete");
+        assert_eq!(entries[&135].author.as_deref(), Some("Victor Hugo"));
+        assert_eq!(entries[&135].other["Translator"], "Isabel F. Hapgood");
+    }
+
+    #[test]
+    fn test_index_junk_and_duplicates() {
+        let input_string = "TITLE and AUTHOR                                     EBOOK NO.
+
+The cat in the convent, by Various                                       72471
+Unindented junk, not part of the title
+ [Language: English]
+
+Ennen ja nyt: Kertomus, by Kaarlo Kramsu                                 72470
+[Language: Finnish]
+
+The cat in the convent, reposted                                         72471
+";
+        let parsed = parse_index(input_string);
+        assert_eq!(
+            parsed.unparsed,
+            vec![UnparsedLine {
+                line: 4,
+                text: "Unindented junk, not part of the title".to_string()
+            }]
+        );
+        assert_eq!(
+            parsed.duplicates,
+            vec![DuplicateEntry {
+                id: 72471,
+                line: 10,
+                text: "The cat in the convent, reposted".to_string()
+            }]
+        );
+        let entries = parsed.into_entries();
+        assert_eq!(entries[&72471].title, "The cat in the convent");
+        assert_eq!(entries[&72471].language.as_deref(), Some("English"));
+        assert_eq!(entries[&72470].language.as_deref(), Some("Finnish"));
+    }
 }
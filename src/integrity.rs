@@ -0,0 +1,90 @@
+use anyhow::{anyhow, Error};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Opens `path` as a zip archive, which reads the central directory. With
+/// `crc` every entry is also decompressed and checked against its CRC.
+pub fn check_zip(path: &Path, crc: bool) -> Result<(), Error> {
+    let file = fs::File::open(path)?;
+    let mut archive =
+        zip::ZipArchive::new(file).map_err(|e| anyhow!("Not a readable zip archive: {}", e))?;
+    if archive.is_empty() {
+        return Err(anyhow!("Zip archive has no entries"));
+    }
+    if crc {
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            // The zip reader compares the CRC once the entry is read to the end
+            io::copy(&mut entry, &mut io::sink())
+                .map_err(|e| anyhow!("{}: {}", entry.name(), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves a bad file to `quarantine_dir/relative`, out of the way of the
+/// next download and of `process`. Returns where it ended up.
+pub fn quarantine(file: &Path, quarantine_dir: &Path, relative: &str) -> Result<PathBuf, Error> {
+    let target = quarantine_dir.join(relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // rename fails across filesystems, fall back to a copy
+    if fs::rename(file, &target).is_err() {
+        fs::copy(file, &target)?;
+        fs::remove_file(file)?;
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn write_zip(path: &Path, content: &[u8]) {
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("1234.txt", options).unwrap();
+        zip.write_all(content).unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_check_zip() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("1234.zip");
+        let content = b"The Project Gutenberg eBook of Submerged Forests";
+        write_zip(&path, content);
+        assert!(check_zip(&path, true).is_ok());
+
+        // Flip a byte of the stored text, the directory is still fine
+        let mut bytes = fs::read(&path).unwrap();
+        let at = bytes
+            .windows(content.len())
+            .position(|w| w == content)
+            .unwrap();
+        bytes[at] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        assert!(check_zip(&path, false).is_ok());
+        assert!(check_zip(&path, true).is_err());
+
+        // Truncated download
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(check_zip(&path, false).is_err());
+
+        // Error page saved under the zip's name
+        fs::write(&path, "<html><body>503 Service Unavailable</body></html>").unwrap();
+        assert!(check_zip(&path, false).is_err());
+
+        let quarantine_dir = dir.join("quarantine");
+        let moved = quarantine(&path, &quarantine_dir, "1/2/3/1234/1234.zip").unwrap();
+        assert_eq!(moved, quarantine_dir.join("1/2/3/1234/1234.zip"));
+        assert!(moved.exists());
+        assert!(!path.exists());
+    }
+}
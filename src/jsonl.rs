@@ -0,0 +1,34 @@
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Reads a JSONL file, one value per line. Blank lines are skipped and
+/// errors name the line they are on.
+pub fn read<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<Vec<T>, anyhow::Error> {
+    let path = path.as_ref();
+    let reader = BufReader::new(fs::File::open(path)?);
+    let mut values = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = serde_json::from_str(&line)
+            .map_err(|e| anyhow!("{}:{}: {}", path.display(), number + 1, e))?;
+        values.push(value);
+    }
+    Ok(values)
+}
+
+pub fn write<T: Serialize>(path: impl AsRef<Path>, values: &[T]) -> Result<(), anyhow::Error> {
+    let mut out_buf = BufWriter::new(fs::File::create(path)?);
+    for value in values {
+        serde_json::to_writer(&mut out_buf, value)?;
+        writeln!(out_buf)?;
+    }
+    out_buf.flush()?;
+    Ok(())
+}
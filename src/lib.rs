@@ -1,5 +1,18 @@
+pub mod catalog;
 pub mod config;
+pub mod crawler;
 pub mod downloader;
+pub mod ebook_ids;
 pub mod index;
+pub mod integrity;
+pub mod jsonl;
+pub mod manifest;
+pub mod mirrors;
 pub mod parser;
 pub mod postprocess;
+pub mod report;
+pub mod retry;
+pub mod robots;
+pub mod shutdown;
+#[cfg(test)]
+mod test_server;
@@ -0,0 +1,400 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ebook_ids::ebook_dir;
+use crate::jsonl;
+use crate::parser::encoding_variant;
+
+/// What kind of file a manifest entry points at, from its extension
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    Zip,
+    Txt,
+    Html,
+    Epub,
+    Mobi,
+    Pdf,
+    Rdf,
+    Other,
+}
+
+impl FileKind {
+    pub fn from_path(path: &str) -> Self {
+        let extension = path
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "zip" => FileKind::Zip,
+            "txt" => FileKind::Txt,
+            "htm" | "html" => FileKind::Html,
+            "epub" => FileKind::Epub,
+            "mobi" => FileKind::Mobi,
+            "pdf" => FileKind::Pdf,
+            "rdf" => FileKind::Rdf,
+            _ => FileKind::Other,
+        }
+    }
+}
+
+/// Gutenberg's naming for the character set of a text: `N.txt` is ASCII,
+/// `N-8.txt` is ISO-8859 and `N-0.txt` is UTF-8
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Ascii,
+    Iso8859,
+    Utf8,
+}
+
+/// One downloadable file from the mirror
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub url: String,
+    /// Path relative to the mirror root, also used under the download folder
+    pub path: String,
+    pub ebook: Option<u32>,
+    pub kind: FileKind,
+    pub encoding: Option<Encoding>,
+    pub size: Option<u64>,
+    pub modified: Option<String>,
+}
+
+impl ManifestEntry {
+    pub fn new(base_url: &str, url: &str, size: Option<u64>, modified: Option<String>) -> Self {
+        let path = url.strip_prefix(base_url).unwrap_or(url);
+        let path = path.trim_start_matches('/').to_string();
+        let file_name = path.rsplit('/').next().unwrap_or_default();
+        let kind = FileKind::from_path(file_name);
+        let stem = file_name
+            .rsplit_once('.')
+            .map(|(stem, _)| stem)
+            .unwrap_or(file_name);
+        ManifestEntry {
+            url: url.to_string(),
+            ebook: ebook_number(&path, stem),
+            encoding: encoding_variant(file_name),
+            kind,
+            path,
+            size,
+            modified,
+        }
+    }
+
+    /// `modified` as a point in time, listings have minute precision
+    pub fn modified_time(&self) -> Option<SystemTime> {
+        parse_listing_time(self.modified.as_deref()?)
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Reads "2019-02-24 07:42" or "24-Feb-2019 07:42" as UTC
+pub fn parse_listing_time(text: &str) -> Option<SystemTime> {
+    let (date, time) = match text.split_once(' ') {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let parts: Vec<&str> = date.split('-').collect();
+    let [first, second, third] = parts[..] else {
+        return None;
+    };
+    let (year, month, day): (i64, u32, u32) = match MONTHS.iter().position(|m| *m == second) {
+        Some(month) => (third.parse().ok()?, month as u32 + 1, first.parse().ok()?),
+        None => (
+            first.parse().ok()?,
+            second.parse().ok()?,
+            third.parse().ok()?,
+        ),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let (hour, minute): (u64, u64) = match time {
+        Some(time) => {
+            let (hour, minute) = time.split_once(':')?;
+            (hour.parse().ok()?, minute.parse().ok()?)
+        }
+        None => (0, 0),
+    };
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60))
+}
+
+/// The reverse of [`parse_listing_time`], "2019-02-24 07:42" in UTC
+pub fn format_listing_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let minutes = secs % 86400 / 60;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The inverse of days_from_civil
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// "1/2/3/1234/1234-0.zip" -> 1234, falling back to the folder name
+fn ebook_number(path: &str, stem: &str) -> Option<u32> {
+    let digits: String = stem.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if let Ok(number) = digits.parse() {
+        return Some(number);
+    }
+    path.rsplit('/').nth(1).and_then(|dir| dir.parse().ok())
+}
+
+/// Reads a JSONL manifest, one entry per line
+pub fn read_manifest(path: impl AsRef<Path>) -> Result<Vec<ManifestEntry>, anyhow::Error> {
+    jsonl::read(path)
+}
+
+pub fn write_manifest(
+    path: impl AsRef<Path>,
+    entries: &[ManifestEntry],
+) -> Result<(), anyhow::Error> {
+    jsonl::write(path, entries)
+}
+
+/// What changed between two crawls of the mirror
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ManifestDiff {
+    pub added: Vec<ManifestEntry>,
+    pub removed: Vec<ManifestEntry>,
+    pub changed: Vec<ManifestEntry>,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Entries that need to be fetched to bring a download folder up to date
+    pub fn to_download(&self) -> Vec<ManifestEntry> {
+        self.added
+            .iter()
+            .chain(self.changed.iter())
+            .cloned()
+            .collect()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let file = fs::File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        let mut out_buf = BufWriter::new(fs::File::create(path)?);
+        serde_json::to_writer(&mut out_buf, self)?;
+        out_buf.flush()?;
+        Ok(())
+    }
+}
+
+/// Compares two manifests by url. A file counts as changed when its size
+/// or last modified time differs.
+pub fn diff(old: &[ManifestEntry], new: &[ManifestEntry]) -> ManifestDiff {
+    let old_by_url: HashMap<&str, &ManifestEntry> =
+        old.iter().map(|file| (file.url.as_str(), file)).collect();
+    let new_by_url: HashMap<&str, &ManifestEntry> =
+        new.iter().map(|file| (file.url.as_str(), file)).collect();
+
+    let mut result = ManifestDiff::default();
+    for file in new {
+        match old_by_url.get(file.url.as_str()) {
+            None => result.added.push(file.clone()),
+            Some(previous) => {
+                if previous.size != file.size || previous.modified != file.modified {
+                    result.changed.push(file.clone());
+                }
+            }
+        }
+    }
+    for file in old {
+        if !new_by_url.contains_key(file.url.as_str()) {
+            result.removed.push(file.clone());
+        }
+    }
+    result
+}
+
+// Whether an entry lives in one of the ebook folders, subfolders included
+fn in_ebooks(entry: &ManifestEntry, folders: &HashSet<String>) -> bool {
+    entry
+        .path
+        .match_indices('/')
+        .any(|(end, _)| folders.contains(&entry.path[..=end]))
+}
+
+/// The entries of `entries` that belong to one of `ids`
+pub fn only_ebooks(entries: &[ManifestEntry], ids: &[u32]) -> Vec<ManifestEntry> {
+    let folders: HashSet<String> = ids.iter().map(|&id| ebook_dir(id)).collect();
+    entries
+        .iter()
+        .filter(|entry| in_ebooks(entry, &folders))
+        .cloned()
+        .collect()
+}
+
+/// The entries of `entries` below one of the directory urls in `directories`
+pub fn under_directories(entries: &[ManifestEntry], directories: &[String]) -> Vec<ManifestEntry> {
+    entries
+        .iter()
+        .filter(|entry| {
+            directories
+                .iter()
+                .any(|directory| entry.url.starts_with(directory.as_str()))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Replaces the entries of `ids` in `previous` with a fresh crawl of just
+/// those ebooks, everything else is kept as it was
+pub fn merge_ebooks(
+    previous: Vec<ManifestEntry>,
+    crawled: &[ManifestEntry],
+    ids: &[u32],
+) -> Vec<ManifestEntry> {
+    let folders: HashSet<String> = ids.iter().map(|&id| ebook_dir(id)).collect();
+    let mut merged: Vec<ManifestEntry> = previous
+        .into_iter()
+        .filter(|entry| !in_ebooks(entry, &folders))
+        .collect();
+    merged.extend_from_slice(crawled);
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(url: &str, size: u64) -> ManifestEntry {
+        ManifestEntry::new(
+            "https://abc.0/",
+            url,
+            Some(size),
+            Some("2024-01-01 00:00".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_new_entry() {
+        let entry = file("https://abc.0/1/2/3/1234/1234-0.zip", 10);
+        assert_eq!(entry.path, "1/2/3/1234/1234-0.zip");
+        assert_eq!(entry.ebook, Some(1234));
+        assert_eq!(entry.kind, FileKind::Zip);
+        assert_eq!(entry.encoding, Some(Encoding::Utf8));
+
+        let entry = file("https://abc.0/1/2/3/1234/1234-8.txt", 10);
+        assert_eq!(entry.encoding, Some(Encoding::Iso8859));
+        let entry = file("https://abc.0/1/2/3/1234/1234.txt", 10);
+        assert_eq!(entry.encoding, Some(Encoding::Ascii));
+
+        let entry = file("https://abc.0/1/2/3/1234/1234-h.zip", 10);
+        assert_eq!(entry.encoding, None);
+        let entry = file("https://abc.0/1/2/3/1234/images/cover.jpg", 10);
+        assert_eq!(entry.kind, FileKind::Other);
+        assert_eq!(entry.ebook, None);
+        let entry = file("https://abc.0/1/2/3/1234/readme.htm", 10);
+        assert_eq!(entry.kind, FileKind::Html);
+        assert_eq!(entry.ebook, Some(1234));
+    }
+
+    #[test]
+    fn test_parse_listing_time() {
+        let expected = UNIX_EPOCH + Duration::from_secs(1551505320);
+        assert_eq!(parse_listing_time("2019-03-02 05:42"), Some(expected));
+        assert_eq!(parse_listing_time("02-Mar-2019 05:42"), Some(expected));
+        assert_eq!(
+            parse_listing_time("1970-01-02"),
+            Some(UNIX_EPOCH + Duration::from_secs(86400))
+        );
+        assert_eq!(parse_listing_time("yesterday"), None);
+        assert_eq!(format_listing_time(expected), "2019-03-02 05:42");
+        assert_eq!(
+            format_listing_time(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "2000-02-29 00:00"
+        );
+        assert_eq!(parse_listing_time("2019-13-02 05:42"), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = "https://abc.0/a.zip";
+        let b = "https://abc.0/b.zip";
+        let old = vec![file(a, 1), file(b, 2), file("https://abc.0/c.zip", 3)];
+        let new = vec![file(a, 1), file(b, 20), file("https://abc.0/d.zip", 4)];
+        let result = diff(&old, &new);
+        assert_eq!(result.added, vec![file("https://abc.0/d.zip", 4)]);
+        assert_eq!(result.removed, vec![file("https://abc.0/c.zip", 3)]);
+        assert_eq!(result.changed, vec![file(b, 20)]);
+        assert_eq!(result.to_download().len(), 2);
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_merge_ebooks() {
+        let old = vec![
+            file("https://abc.0/1/12/12.zip", 1),
+            file("https://abc.0/1/2/123/123.zip", 2),
+            file("https://abc.0/1/2/123/123-h/123-h.htm", 3),
+        ];
+        let crawled = vec![file("https://abc.0/1/2/123/123.zip", 20)];
+        assert_eq!(only_ebooks(&old, &[123]).len(), 2);
+        assert_eq!(
+            under_directories(&old, &["https://abc.0/1/2/".to_string()]).len(),
+            2
+        );
+        let merged = merge_ebooks(old, &crawled, &[123]);
+        assert_eq!(
+            merged,
+            vec![
+                file("https://abc.0/1/12/12.zip", 1),
+                file("https://abc.0/1/2/123/123.zip", 20),
+            ]
+        );
+    }
+}
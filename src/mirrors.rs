@@ -0,0 +1,193 @@
+use anyhow::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::Config;
+use crate::downloader::url_suffix;
+
+// Default for `mirror_max_failures`
+const DEFAULT_MAX_FAILURES: usize = 3;
+
+struct Mirror {
+    url: String,
+    // Consecutive failures, reset on success
+    failures: AtomicUsize,
+}
+
+/// The primary `url` plus the configured `mirrors`. Requests are spread
+/// round robin over the healthy ones, and a mirror that fails
+/// `max_failures` times in a row is skipped until it succeeds again.
+pub struct MirrorPool {
+    primary: String,
+    mirrors: Vec<Mirror>,
+    next: AtomicUsize,
+    max_failures: usize,
+}
+
+impl MirrorPool {
+    pub fn new(primary: &str, mirrors: &[String], max_failures: usize) -> Self {
+        let mut urls: Vec<String> = vec![primary.to_string()];
+        for mirror in mirrors {
+            if !urls.contains(mirror) {
+                urls.push(mirror.clone());
+            }
+        }
+        MirrorPool {
+            primary: primary.to_string(),
+            mirrors: urls
+                .into_iter()
+                .map(|url| Mirror {
+                    url,
+                    failures: AtomicUsize::new(0),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+            max_failures: max_failures.max(1),
+        }
+    }
+
+    /// A local `url` is used on its own, the other mirrors are HTTP
+    pub fn from_config(config: &Config) -> Self {
+        let mirrors = match config.local_root() {
+            Some(_) => &[],
+            None => config.mirrors.as_deref().unwrap_or_default(),
+        };
+        MirrorPool::new(
+            &config.url,
+            mirrors,
+            config.mirror_max_failures.unwrap_or(DEFAULT_MAX_FAILURES),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.mirrors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mirrors.is_empty()
+    }
+
+    fn is_healthy(&self, mirror: &Mirror) -> bool {
+        mirror.failures.load(Ordering::Relaxed) < self.max_failures
+    }
+
+    /// The next healthy mirror. When every mirror is unhealthy the one
+    /// with the fewest failures is used, so work never stops entirely.
+    pub fn pick(&self) -> &str {
+        self.pick_untried(&[])
+    }
+
+    /// Like [`pick`](Self::pick) but passes over the mirrors in `tried`
+    /// while any other is left, so failing over never asks the same mirror
+    /// twice. The round robin is shared by every task and cannot promise that.
+    pub fn pick_untried(&self, tried: &[String]) -> &str {
+        let mut candidates: Vec<&Mirror> = self
+            .mirrors
+            .iter()
+            .filter(|mirror| !tried.contains(&mirror.url))
+            .collect();
+        if candidates.is_empty() {
+            candidates = self.mirrors.iter().collect();
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..candidates.len() {
+            let mirror = candidates[(start + offset) % candidates.len()];
+            if self.is_healthy(mirror) {
+                return &mirror.url;
+            }
+        }
+        &candidates
+            .iter()
+            .min_by_key(|mirror| mirror.failures.load(Ordering::Relaxed))
+            .expect("the primary url is always in the pool")
+            .url
+    }
+
+    /// Moves a url under the primary `url` onto `mirror`
+    pub fn rewrite(&self, url: &str, mirror: &str) -> Result<String, Error> {
+        let suffix = url_suffix(url, &self.primary)?;
+        let mirror = mirror.trim_end_matches('/');
+        Ok(format!("{}/{}", mirror, suffix.trim_start_matches('/')))
+    }
+
+    pub fn report_success(&self, mirror: &str) {
+        if let Some(mirror) = self.mirrors.iter().find(|m| m.url == mirror) {
+            mirror.failures.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn report_failure(&self, mirror: &str) {
+        if let Some(mirror) = self.mirrors.iter().find(|m| m.url == mirror) {
+            let failures = mirror.failures.fetch_add(1, Ordering::Relaxed) + 1;
+            if failures == self.max_failures {
+                log::warn!(
+                    "Mirror {} marked unhealthy after {} failures",
+                    mirror.url,
+                    failures
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_pool() {
+        let pool = MirrorPool::new(
+            "https://a.0/",
+            &[
+                "https://b.0/mirror/".to_string(),
+                "https://a.0/".to_string(),
+            ],
+            2,
+        );
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.pick(), "https://a.0/");
+        assert_eq!(pool.pick(), "https://b.0/mirror/");
+
+        pool.report_failure("https://a.0/");
+        pool.report_failure("https://a.0/");
+        assert_eq!(pool.pick(), "https://b.0/mirror/");
+        assert_eq!(pool.pick(), "https://b.0/mirror/");
+
+        // everything unhealthy, fall back to the least failed mirror
+        for _ in 0..3 {
+            pool.report_failure("https://b.0/mirror/");
+        }
+        assert_eq!(pool.pick(), "https://a.0/");
+        pool.report_success("https://b.0/mirror/");
+        assert_eq!(pool.pick(), "https://b.0/mirror/");
+    }
+
+    #[test]
+    fn test_pick_untried() {
+        let pool = MirrorPool::new(
+            "https://a.0/",
+            &["https://b.0/".to_string(), "https://c.0/".to_string()],
+            2,
+        );
+        let mut tried = Vec::new();
+        for _ in 0..3 {
+            // Another task moving the round robin along changes nothing
+            pool.pick();
+            tried.push(pool.pick_untried(&tried).to_string());
+        }
+        tried.sort();
+        assert_eq!(tried, vec!["https://a.0/", "https://b.0/", "https://c.0/"]);
+        // All tried, back to the plain round robin
+        assert!(!pool.pick_untried(&tried).is_empty());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let pool = MirrorPool::new("https://a.0/", &[], 3);
+        assert_eq!(
+            pool.rewrite("https://a.0/1/2/12.zip", "https://b.0/mirror/")
+                .unwrap(),
+            "https://b.0/mirror/1/2/12.zip"
+        );
+        assert!(pool.rewrite("https://c.0/1.zip", "https://b.0/").is_err());
+    }
+}
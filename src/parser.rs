@@ -1,10 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use select::document::Document;
+use select::node::Node;
+use select::predicate::Name;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::manifest::Encoding;
 
 // Define a struct to hold both types of links
 #[derive(Debug)]
 pub struct ExtractedLinks {
     pub directories: Vec<String>, // Links ending with "/" for crawling
     pub files: Vec<String>,       // Links not ending with "/" (objects)
+    pub excluded: Vec<String>,    // Directories skipped by PathExcludes
 }
 
 impl ExtractedLinks {
@@ -16,86 +23,348 @@ impl ExtractedLinks {
     }
 }
 
-// this filters the zip and the source files
-pub fn filter_href(current_link: &str, href: Vec<&str>) -> ExtractedLinks {
-    // if .zip and .txt exist, take .zip
-    // if the zip is for the folder, remove the folder
-    let mut base_to_path: HashMap<&str, &str> = HashMap::new();
+/// One row of an Apache autoindex page
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingEntry {
+    pub href: String,
+    pub size: Option<u64>,
+    pub modified: Option<String>,
+}
 
-    // First pass: collect all paths by their base name (without extension)
-    for &path in &href {
-        let base_name = if let Some(stripped) = path.strip_suffix('/') {
-            // For directories, remove trailing slash
-            stripped
-        } else if let Some(last_dot_pos) = path.rfind('.') {
-            // For files, remove extension
-            &path[..last_dot_pos]
-        } else {
-            // No extension or trailing slash
-            path
+/// Reads the links of a directory listing together with the "Last modified"
+/// and "Size" columns. Handles both the `<pre>` and the `<table>` layouts.
+pub fn parse_listing(html: &str) -> Vec<ListingEntry> {
+    let document = Document::from(html);
+    let mut entries = Vec::new();
+    for link in document.find(Name("a")) {
+        let Some(href) = link.attr("href") else {
+            continue;
         };
+        // Skip parent directory links and the column sorting links
+        if href == "../" || href.starts_with('/') || href.starts_with('?') {
+            continue;
+        }
+        let (modified, size) = parse_columns(&row_text(&link));
+        entries.push(ListingEntry {
+            href: href.to_string(),
+            size,
+            modified,
+        });
+    }
+    entries
+}
 
-        // Check if we already have an entry for this base name
-        if let Some(existing_path) = base_to_path.get(base_name) {
-            // If the existing path is not a zip and the current one is, replace it
-            if !existing_path.ends_with(".zip") && path.ends_with(".zip") {
-                base_to_path.insert(base_name, path);
+// The text that follows the link on the same row
+fn row_text(link: &Node) -> String {
+    match link.parent() {
+        Some(cell) if cell.is(Name("td")) => {
+            let mut text = String::new();
+            let mut next = cell.next();
+            while let Some(node) = next {
+                text.push(' ');
+                text.push_str(&node.text());
+                next = node.next();
             }
-        } else {
-            // No existing entry, add this one
-            base_to_path.insert(base_name, path);
+            text
         }
+        _ => match link.next() {
+            Some(node) if node.as_text().is_some() => node.text(),
+            _ => String::new(),
+        },
     }
+}
 
-    // Second pass: handle the case where we have both a folder and a zip file for it
-    let mut result = ExtractedLinks {
-        directories: Vec::new(),
-        files: Vec::new(),
+// "2019-02-24 07:42  152K" -> (Some("2019-02-24 07:42"), Some(155648))
+fn parse_columns(text: &str) -> (Option<String>, Option<u64>) {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let Some(date_pos) = tokens
+        .iter()
+        .position(|t| t.chars().next().is_some_and(|c| c.is_ascii_digit()) && t.contains('-'))
+    else {
+        return (None, None);
     };
-    let mut folder_bases = HashSet::new();
+    let modified = match tokens.get(date_pos + 1) {
+        Some(time) if time.contains(':') => format!("{} {}", tokens[date_pos], time),
+        _ => tokens[date_pos].to_string(),
+    };
+    let size_pos = if modified.contains(' ') {
+        date_pos + 2
+    } else {
+        date_pos + 1
+    };
+    let size = tokens.get(size_pos).and_then(|t| parse_size(t));
+    (Some(modified), size)
+}
 
-    // Collect all folder base names
-    for &path in &href {
-        if let Some(stripped) = path.strip_suffix('/') {
-            folder_bases.insert(stripped);
+// Apache prints sizes as "512", "152K", "1.2M" or "-" for directories
+fn parse_size(size: &str) -> Option<u64> {
+    let (number, multiplier) = match size.chars().last()? {
+        'K' | 'k' => (&size[..size.len() - 1], 1024.0),
+        'M' => (&size[..size.len() - 1], 1024.0 * 1024.0),
+        'G' => (&size[..size.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (size, 1.0),
+    };
+    let number: f64 = number.parse().ok()?;
+    Some((number * multiplier) as u64)
+}
+
+/// Which representation of an ebook to keep when a folder offers several.
+/// Extensions are compared without the dot and ignoring case, patterns are
+/// globs matched against the file name. Directories are never filtered.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormatPolicy {
+    /// Extensions in order of preference, anything else ranks after them
+    pub prefer: Vec<String>,
+    /// Only keep these extensions, empty keeps everything
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Only keep file names matching one of these, empty keeps everything
+    pub include_patterns: GlobList,
+    pub exclude_patterns: GlobList,
+    /// `N.txt`, `N-8.txt` and `N-0.txt` are one ebook, only the first
+    /// encoding found in this order is kept
+    pub encodings: Vec<Encoding>,
+}
+
+impl Default for FormatPolicy {
+    // if .zip and .txt exist, take .zip
+    fn default() -> Self {
+        FormatPolicy {
+            prefer: vec!["zip".to_string()],
+            include: Vec::new(),
+            exclude: Vec::new(),
+            include_patterns: GlobList::default(),
+            exclude_patterns: GlobList::default(),
+            encodings: vec![Encoding::Utf8, Encoding::Iso8859, Encoding::Ascii],
         }
     }
+}
 
-    // Build the result, prioritizing zips over folders with the same name
-    for &path in &href {
-        let base = if let Some(stripped) = path.strip_suffix('/') {
-            // For directories, remove trailing slash
-            stripped
-        } else if let Some(last_dot_pos) = path.rfind('.') {
-            // For files, remove extension
-            &path[..last_dot_pos]
-        } else {
-            // No extension or trailing slash
-            path
-        };
+/// Glob patterns compiled once when the list is built. Invalid ones are
+/// logged and skipped, `Config::validate` reports them by their source.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(from = "Vec<String>")]
+pub struct GlobList {
+    sources: Vec<String>,
+    patterns: Vec<glob::Pattern>,
+}
 
-        // If this is a folder and we have a zip for it, skip the folder
-        if path.ends_with('/') && href.iter().any(|&p| p == format!("{}.zip", base)) {
-            continue;
+impl GlobList {
+    pub fn new(sources: &[&str]) -> Self {
+        sources
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    /// The patterns as written in the config, including invalid ones
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(name))
+    }
+}
+
+impl From<Vec<String>> for GlobList {
+    fn from(sources: Vec<String>) -> Self {
+        let patterns = sources
+            .iter()
+            .filter_map(|pattern| match glob::Pattern::new(pattern) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    log::warn!("Ignoring format pattern {:?}: {}", pattern, e);
+                    None
+                }
+            })
+            .collect();
+        GlobList { sources, patterns }
+    }
+}
+
+impl FormatPolicy {
+    /// Whether a file passes the include and exclude lists
+    pub fn allows(&self, file_name: &str) -> bool {
+        let extension = extension(file_name);
+        let listed = |list: &[String]| list.iter().any(|e| e.eq_ignore_ascii_case(extension));
+        if !self.include.is_empty() && !listed(&self.include) {
+            return false;
         }
+        if listed(&self.exclude) {
+            return false;
+        }
+        if !self.include_patterns.is_empty() && !self.include_patterns.matches(file_name) {
+            return false;
+        }
+        !self.exclude_patterns.matches(file_name)
+    }
+
+    /// Lower is better
+    pub fn rank(&self, file_name: &str) -> usize {
+        let extension = extension(file_name);
+        self.prefer
+            .iter()
+            .position(|e| e.eq_ignore_ascii_case(extension))
+            .unwrap_or(self.prefer.len())
+    }
+
+    /// Lower is better, `None` for files without an encoding variant
+    pub fn encoding_rank(&self, file_name: &str) -> Option<usize> {
+        let encoding = encoding_variant(file_name)?;
+        Some(
+            self.encodings
+                .iter()
+                .position(|e| *e == encoding)
+                .unwrap_or(self.encodings.len()),
+        )
+    }
+}
 
-        // If this is a non-zip file and we have a zip for it, skip this file
-        if !path.ends_with('/')
-            && !path.ends_with(".zip")
-            && href.iter().any(|&p| p == format!("{}.zip", base))
+/// Gutenberg's encoding suffix on plain text and its zips:
+/// `N.txt` is ASCII, `N-8.txt` is ISO-8859 and `N-0.txt` is UTF-8
+pub fn encoding_variant(file_name: &str) -> Option<Encoding> {
+    let extension = extension(file_name);
+    if !extension.eq_ignore_ascii_case("txt") && !extension.eq_ignore_ascii_case("zip") {
+        return None;
+    }
+    match base_name(file_name).rsplit_once('-') {
+        Some((_, "0")) => Some(Encoding::Utf8),
+        Some((_, "8")) => Some(Encoding::Iso8859),
+        Some(_) => None,
+        None => Some(Encoding::Ascii),
+    }
+}
+
+// The name shared by every variant of an ebook, "1234-0.txt" -> "1234"
+fn ebook_key(path: &str) -> &str {
+    let base = base_name(path);
+    match encoding_variant(path) {
+        Some(Encoding::Utf8) | Some(Encoding::Iso8859) => &base[..base.len() - 2],
+        _ => base,
+    }
+}
+
+fn extension(file_name: &str) -> &str {
+    file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .unwrap_or_default()
+}
+
+// For files, remove extension
+fn base_name(path: &str) -> &str {
+    match path.rfind('.') {
+        Some(last_dot_pos) => &path[..last_dot_pos],
+        // No extension
+        None => path,
+    }
+}
+
+/// Directories the crawler never descends into, such as `old/` or the
+/// historical `etextNN/` trees. Each pattern is a glob matched against the
+/// folder name and against its path from the mirror root.
+#[derive(Debug, Clone, Default)]
+pub struct PathExcludes {
+    patterns: Vec<glob::Pattern>,
+}
+
+impl PathExcludes {
+    pub fn new(patterns: &[String]) -> Self {
+        let patterns = patterns
+            .iter()
+            .filter_map(
+                |pattern| match glob::Pattern::new(pattern.trim_end_matches('/')) {
+                    Ok(pattern) => Some(pattern),
+                    Err(e) => {
+                        log::warn!("Ignoring exclude pattern {:?}: {}", pattern, e);
+                        None
+                    }
+                },
+            )
+            .collect();
+        PathExcludes { patterns }
+    }
+
+    pub fn is_excluded(&self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.matches(name) || pattern.matches(path))
+    }
+}
+
+// this filters the zip and the source files
+pub fn filter_href(
+    current_link: &str,
+    href: Vec<&str>,
+    policy: &FormatPolicy,
+    excludes: &PathExcludes,
+) -> ExtractedLinks {
+    let candidates: Vec<&str> = href
+        .iter()
+        .copied()
+        .filter(|path| !path.ends_with('/') && policy.allows(path))
+        .collect();
+
+    // First pass: find the best encoding offered for each ebook
+    let mut best_encoding: HashMap<&str, usize> = HashMap::new();
+    for &path in &candidates {
+        if let Some(rank) = policy.encoding_rank(path) {
+            let best = best_encoding.entry(ebook_key(path)).or_insert(rank);
+            *best = (*best).min(rank);
+        }
+    }
+
+    // Second pass: among the files in that encoding (and those without
+    // one), pick the preferred format for each ebook
+    let mut base_to_path: HashMap<&str, &str> = HashMap::new();
+    for &path in &candidates {
+        let base = ebook_key(path);
+        if policy
+            .encoding_rank(path)
+            .is_some_and(|rank| Some(&rank) != best_encoding.get(base))
         {
             continue;
         }
+        match base_to_path.get(base) {
+            // Ties keep the first one listed
+            Some(existing) if policy.rank(existing) <= policy.rank(path) => {}
+            _ => {
+                base_to_path.insert(base, path);
+            }
+        }
+    }
 
-        // Add to result if it's the preferred version for this base name
-        if base_to_path.get(base) == Some(&path) {
-            // if is a folder, add to directories
-            let path = format! {"{}{}", current_link, path};
-            if path.ends_with('/') {
-                result.directories.push(path.to_string());
-            } else {
-                result.files.push(path.to_string());
+    // Last pass: build the result in listing order
+    let mut result = ExtractedLinks {
+        directories: Vec::new(),
+        files: Vec::new(),
+        excluded: Vec::new(),
+    };
+    for &path in &href {
+        if let Some(folder) = path.strip_suffix('/') {
+            if excludes.is_excluded(folder) {
+                result.excluded.push(format!("{}{}", current_link, path));
+                continue;
+            }
+            // If the zip of this folder was kept, skip the folder
+            if base_to_path
+                .get(ebook_key(folder))
+                .is_some_and(|chosen| extension(chosen).eq_ignore_ascii_case("zip"))
+            {
+                continue;
             }
+            result.directories.push(format!("{}{}", current_link, path));
+        } else if base_to_path.get(ebook_key(path)) == Some(&path) {
+            result.files.push(format!("{}{}", current_link, path));
         }
     }
 
@@ -109,7 +378,12 @@ mod tests {
     #[test]
     fn test_filter_href() {
         let hrefs = vec!["1.zip", "1.txt", "2.zip", "2/", "3/"];
-        let filtered = filter_href("", hrefs);
+        let filtered = filter_href(
+            "",
+            hrefs,
+            &FormatPolicy::default(),
+            &PathExcludes::default(),
+        );
 
         println!("{:?}", filtered);
         // contain 3/ only
@@ -117,4 +391,137 @@ mod tests {
         // contain 1.zip, 2.zip
         assert_eq!(filtered.files.len(), 2);
     }
+
+    #[test]
+    fn test_filter_href_policy() {
+        let hrefs = vec![
+            "1.zip", "1.txt", "1.epub", "2-h.zip", "2-h/", "2.txt", "3.html", "3.txt",
+        ];
+
+        // HTML only, the html zip is excluded so its folder gets crawled
+        let html_only = FormatPolicy {
+            include: vec!["html".to_string(), "htm".to_string()],
+            ..Default::default()
+        };
+        let filtered = filter_href("", hrefs.clone(), &html_only, &PathExcludes::default());
+        assert_eq!(filtered.directories, vec!["2-h/"]);
+        assert_eq!(filtered.files, vec!["3.html"]);
+
+        // EPUB first, then txt
+        let epub_first = FormatPolicy {
+            prefer: vec!["epub".to_string(), "txt".to_string()],
+            exclude_patterns: GlobList::new(&["*-h.zip"]),
+            ..Default::default()
+        };
+        let filtered = filter_href("", hrefs.clone(), &epub_first, &PathExcludes::default());
+        assert_eq!(filtered.directories, vec!["2-h/"]);
+        assert_eq!(filtered.files, vec!["1.epub", "2.txt", "3.txt"]);
+
+        // Only zips, and they replace their folders
+        let zips = FormatPolicy {
+            include_patterns: GlobList::new(&["*.zip"]),
+            ..Default::default()
+        };
+        let filtered = filter_href("", hrefs, &zips, &PathExcludes::default());
+        assert!(filtered.directories.is_empty());
+        assert_eq!(filtered.files, vec!["1.zip", "2-h.zip"]);
+    }
+
+    #[test]
+    fn test_filter_href_encodings() {
+        let hrefs = vec![
+            "10.txt", "10-8.txt", "10-0.txt", "10.zip", "10-8.zip", "10-h.zip", "11.txt",
+            "11-8.txt",
+        ];
+        let filtered = filter_href(
+            "",
+            hrefs.clone(),
+            &FormatPolicy::default(),
+            &PathExcludes::default(),
+        );
+        assert_eq!(filtered.files, vec!["10-0.txt", "10-h.zip", "11-8.txt"]);
+
+        let ascii_first = FormatPolicy {
+            encodings: vec![Encoding::Ascii, Encoding::Iso8859],
+            ..Default::default()
+        };
+        let filtered = filter_href("", hrefs, &ascii_first, &PathExcludes::default());
+        assert_eq!(filtered.files, vec!["10.zip", "10-h.zip", "11.txt"]);
+    }
+
+    #[test]
+    fn test_path_excludes() {
+        let excludes = PathExcludes::new(&[
+            "old".to_string(),
+            "etext[0-9][0-9]/".to_string(),
+            "1/2/3/*".to_string(),
+        ]);
+        assert!(excludes.is_excluded("old"));
+        assert!(excludes.is_excluded("1/2/3/1234/old/"));
+        assert!(excludes.is_excluded("etext05"));
+        assert!(excludes.is_excluded("1/2/3/1234/"));
+        assert!(!excludes.is_excluded("1/2/4/1244/"));
+        assert!(!excludes.is_excluded("older"));
+
+        let hrefs = vec!["1.zip", "old/", "2/"];
+        let filtered = filter_href("1/", hrefs, &FormatPolicy::default(), &excludes);
+        assert_eq!(filtered.directories, vec!["1/2/"]);
+        assert_eq!(filtered.excluded, vec!["1/old/"]);
+    }
+
+    #[test]
+    fn test_glob_list() {
+        let globs: GlobList =
+            toml::from_str::<FormatPolicy>(r#"include_patterns = ["*-0.*", "[z"]"#)
+                .unwrap()
+                .include_patterns;
+        // the invalid pattern is kept for validation but never matches
+        assert_eq!(globs.sources(), ["*-0.*", "[z"]);
+        assert!(globs.matches("12-0.txt"));
+        assert!(!globs.matches("12.txt"));
+        assert!(!globs.matches("[z"));
+    }
+
+    #[test]
+    fn test_encoding_variant() {
+        assert_eq!(encoding_variant("1234-0.txt"), Some(Encoding::Utf8));
+        assert_eq!(encoding_variant("1234-8.zip"), Some(Encoding::Iso8859));
+        assert_eq!(encoding_variant("1234.txt"), Some(Encoding::Ascii));
+        assert_eq!(encoding_variant("1234-h.zip"), None);
+        assert_eq!(encoding_variant("1234-0.html"), None);
+        assert_eq!(ebook_key("1234-0.txt"), "1234");
+        assert_eq!(ebook_key("1234-h.zip"), "1234-h");
+    }
+
+    #[test]
+    fn test_parse_listing() {
+        let pre = r#"<pre><a href="?C=N;O=D">Name</a>  <a href="?C=M;O=A">Last modified</a>  <a href="?C=S;O=A">Size</a><hr><a href="/dirs/1/">Parent Directory</a>                             -
+<a href="10001/">10001/</a>                  2023-05-02 01:08    -
+<a href="10001.zip">10001.zip</a>               2019-02-24 07:42  152K
+<a href="10001.txt">10001.txt</a>               24-Feb-2019 07:42   512
+</pre>"#;
+        let entries = parse_listing(pre);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].href, "10001/");
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[1].modified.as_deref(), Some("2019-02-24 07:42"));
+        assert_eq!(entries[1].size, Some(152 * 1024));
+        assert_eq!(entries[2].modified.as_deref(), Some("24-Feb-2019 07:42"));
+        assert_eq!(entries[2].size, Some(512));
+
+        let table = r#"<table>
+<tr><th><a href="?C=N;O=D">Name</a></th></tr>
+<tr><td><a href="../">Parent Directory</a></td><td>&nbsp;</td><td align="right">  - </td></tr>
+<tr><td><a href="1.zip">1.zip</a></td><td align="right">2021-01-01 10:00  </td><td align="right">1.5M</td><td>&nbsp;</td></tr>
+</table>"#;
+        let entries = parse_listing(table);
+        assert_eq!(
+            entries,
+            vec![ListingEntry {
+                href: "1.zip".to_string(),
+                size: Some(1572864),
+                modified: Some("2021-01-01 10:00".to_string()),
+            }]
+        );
+    }
 }
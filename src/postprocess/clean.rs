@@ -29,7 +29,11 @@ pub fn clean_txt(
     let content = String::from_utf8_lossy(&file_bytes).into();
     let cleaned = strip_headers(content);
 
-    fs::write(dest_file, cleaned)?;
+    // Write next to the destination and rename, so an interrupted run
+    // never leaves a truncated file behind
+    let tmp_file = dest_file.with_extension("tmp");
+    fs::write(&tmp_file, cleaned)?;
+    fs::rename(&tmp_file, &dest_file)?;
 
     Ok(())
 }
@@ -1,14 +1,19 @@
 use std::fs;
 use std::io;
+use std::path::PathBuf;
 
-pub fn unzip(file: &str, _remove: bool) -> Result<(), anyhow::Error> {
+/// Extracts next to the archive and returns the paths of the extracted files
+pub fn unzip(file: &str, _remove: bool) -> Result<Vec<PathBuf>, anyhow::Error> {
     let fname = std::path::Path::new(file);
     let file = fs::File::open(fname)?;
 
-    let mut archive = zip::ZipArchive::new(file).unwrap();
-    let root_folder = fname.parent().unwrap();
+    let mut archive = zip::ZipArchive::new(file)?;
+    let root_folder = fname
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("{} has no parent folder", fname.display()))?;
+    let mut extracted = Vec::new();
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
+        let mut file = archive.by_index(i)?;
         let outpath = match file.enclosed_name() {
             Some(path) => root_folder.join(path),
             None => continue,
@@ -23,7 +28,7 @@ pub fn unzip(file: &str, _remove: bool) -> Result<(), anyhow::Error> {
 
         if file.is_dir() {
             println!("File {} extracted to \"{}\"", i, outpath.display());
-            fs::create_dir_all(&outpath).unwrap();
+            fs::create_dir_all(&outpath)?;
         } else {
             println!(
                 "File {} extracted to \"{}\" ({} bytes)",
@@ -33,11 +38,12 @@ pub fn unzip(file: &str, _remove: bool) -> Result<(), anyhow::Error> {
             );
             if let Some(p) = outpath.parent() {
                 if !p.exists() {
-                    fs::create_dir_all(p).unwrap();
+                    fs::create_dir_all(p)?;
                 }
             }
-            let mut outfile = fs::File::create(&outpath).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
+            let mut outfile = fs::File::create(&outpath)?;
+            io::copy(&mut file, &mut outfile)?;
+            extracted.push(outpath.clone());
         }
 
         // Get and Set permissions
@@ -46,16 +52,24 @@ pub fn unzip(file: &str, _remove: bool) -> Result<(), anyhow::Error> {
             use std::os::unix::fs::PermissionsExt;
 
             if let Some(mode) = file.unix_mode() {
-                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).unwrap();
+                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode))?;
             }
         }
     }
-    Ok(())
+    Ok(extracted)
 }
 
 #[cfg(test)]
 mod tests {
 
+    use super::*;
+
     #[test]
-    fn test_unzip() {}
+    fn test_unzip() {
+        // A corrupt archive is an error, not a panic
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1234.zip");
+        fs::write(&path, "<html>Not Found</html>").unwrap();
+        assert!(unzip(&path.display().to_string(), false).is_err());
+    }
 }
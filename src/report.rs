@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::jsonl;
+
+/// How a single download ended
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadStatus {
+    Downloaded,
+    /// Continued from a `.part` file left by an earlier run
+    Resumed,
+    /// Already on disk with the size and mtime from the manifest
+    Skipped,
+    /// Every mirror was tried and none delivered the file
+    Failed,
+    /// The url could not be turned into a download path
+    Invalid,
+}
+
+impl DownloadStatus {
+    pub fn is_failure(self) -> bool {
+        matches!(self, DownloadStatus::Failed | DownloadStatus::Invalid)
+    }
+}
+
+/// One line of the download report
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DownloadRecord {
+    pub url: String,
+    /// Where the file was written, empty for invalid urls
+    pub path: String,
+    pub status: DownloadStatus,
+    pub http_status: Option<u16>,
+    /// Bytes received in this run, not counting a resumed prefix
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+impl DownloadRecord {
+    pub fn new(url: &str, path: &str) -> Self {
+        DownloadRecord {
+            url: url.to_string(),
+            path: path.to_string(),
+            status: DownloadStatus::Failed,
+            http_status: None,
+            bytes: 0,
+            duration_ms: 0,
+            error: None,
+        }
+    }
+}
+
+/// Urls of the records that did not make it to disk
+pub fn failed_urls(records: &[DownloadRecord]) -> Vec<String> {
+    records
+        .iter()
+        .filter(|record| record.status.is_failure())
+        .map(|record| record.url.clone())
+        .collect()
+}
+
+/// Reads a JSONL report, one record per line
+pub fn read_report(path: impl AsRef<Path>) -> Result<Vec<DownloadRecord>, anyhow::Error> {
+    jsonl::read(path)
+}
+
+pub fn write_report(
+    path: impl AsRef<Path>,
+    records: &[DownloadRecord],
+) -> Result<(), anyhow::Error> {
+    jsonl::write(path, records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_round_trip() {
+        let mut ok = DownloadRecord::new("https://abc.0/1/1.zip", "download/1/1.zip");
+        ok.status = DownloadStatus::Downloaded;
+        ok.http_status = Some(200);
+        ok.bytes = 10;
+        let mut failed = DownloadRecord::new("https://abc.0/2/2.zip", "download/2/2.zip");
+        failed.http_status = Some(404);
+        failed.error = Some("Server returned 404 Not Found".to_string());
+        let mut invalid = DownloadRecord::new("https://other/3.zip", "");
+        invalid.status = DownloadStatus::Invalid;
+        let records = vec![ok, failed, invalid];
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("download_report.jsonl");
+        write_report(&path, &records).unwrap();
+        let read = read_report(&path).unwrap();
+        assert_eq!(read, records);
+        assert_eq!(
+            failed_urls(&read),
+            vec!["https://abc.0/2/2.zip", "https://other/3.zip"]
+        );
+    }
+}
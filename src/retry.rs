@@ -0,0 +1,131 @@
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+// Backoff starts around 100ms and doubles per attempt, capped at a minute
+const DEFAULT_BASE_DELAY_MS: u64 = 100;
+const DEFAULT_MAX_DELAY_SECS: u64 = 60;
+
+/// How often and how long to wait before trying a request again
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: usize) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+            max_delay: Duration::from_secs(DEFAULT_MAX_DELAY_SECS),
+        }
+    }
+
+    /// Exponential backoff with jitter, `attempt` starts at 1
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX));
+        let jitter_ms = self.base_delay.as_millis().max(1) as u64;
+        let jitter = Duration::from_millis(rand::rng().random_range(0..jitter_ms));
+        (exponential + jitter).min(self.max_delay)
+    }
+}
+
+/// Whether a response is worth asking for again
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_request() || error.is_body()
+}
+
+/// Reads `Retry-After` as either delay seconds or an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Calls `send` until it returns a response that is not 429/5xx, or the
+/// attempts run out. Connection errors are retried too. When attempts run
+/// out on a bad status, the last response is returned so the caller can
+/// inspect it.
+pub async fn fetch_with_retry<F, Fut>(
+    policy: &RetryPolicy,
+    url: &str,
+    mut send: F,
+) -> Result<Response, reqwest::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response, reqwest::Error>>,
+{
+    let mut attempts: u32 = 0;
+    loop {
+        attempts += 1;
+        let out_of_attempts = attempts as usize >= policy.max_attempts;
+        let wait = match send().await {
+            Ok(response) => {
+                if !is_retryable_status(response.status()) || out_of_attempts {
+                    return Ok(response);
+                }
+                let wait = retry_after(&response)
+                    .map(|wait| wait.min(policy.max_delay))
+                    .unwrap_or_else(|| policy.backoff(attempts));
+                log::warn!(
+                    "{} returned {}, retrying in {:?}",
+                    url,
+                    response.status(),
+                    wait
+                );
+                wait
+            }
+            Err(e) => {
+                if !is_retryable_error(&e) || out_of_attempts {
+                    return Err(e);
+                }
+                let wait = policy.backoff(attempts);
+                log::warn!("{} failed: {}, retrying in {:?}", url, e, wait);
+                wait
+            }
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(5);
+        for attempt in 1..5 {
+            let wait = policy.backoff(attempt);
+            let floor = Duration::from_millis(100 << attempt);
+            assert!(wait >= floor);
+            assert!(wait < floor + Duration::from_millis(100));
+        }
+        // capped at max_delay
+        assert_eq!(policy.backoff(40), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!is_retryable_status(StatusCode::OK));
+    }
+}
@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+/// The rules from a robots.txt that apply to one user agent
+#[derive(Debug, Default, Clone)]
+pub struct Robots {
+    // (allow, pattern)
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<Duration>,
+}
+
+impl Robots {
+    /// A robots.txt that allows everything, used when the host has none
+    pub fn allow_all() -> Self {
+        Robots::default()
+    }
+
+    /// Picks the group whose `User-agent` matches `user_agent`, falling back to `*`
+    pub fn parse(text: &str, user_agent: &str) -> Self {
+        // The product token is what robots.txt groups match against
+        let token = user_agent
+            .split(['/', ' '])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        let mut specific: Option<Robots> = None;
+        let mut wildcard: Option<Robots> = None;
+        // Agents of the group being read, and whether its rules started
+        let mut agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+        let mut current = Robots::default();
+
+        let mut finish_group = |agents: &[String], group: Robots| {
+            for agent in agents {
+                if agent == "*" {
+                    wildcard.get_or_insert_with(|| group.clone());
+                } else if !token.is_empty() && token.contains(agent.as_str()) {
+                    specific.get_or_insert_with(|| group.clone());
+                }
+            }
+        };
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim();
+            match key.as_str() {
+                "user-agent" => {
+                    if in_rules {
+                        finish_group(&agents, std::mem::take(&mut current));
+                        agents.clear();
+                        in_rules = false;
+                    }
+                    agents.push(value.to_ascii_lowercase());
+                }
+                "allow" | "disallow" => {
+                    in_rules = true;
+                    // An empty Disallow means everything is allowed
+                    if !value.is_empty() {
+                        current.rules.push((key == "allow", value.to_string()));
+                    }
+                }
+                "crawl-delay" => {
+                    in_rules = true;
+                    if let Ok(seconds) = value.parse::<f64>() {
+                        current.crawl_delay = Some(Duration::from_secs_f64(seconds));
+                    }
+                }
+                _ => {}
+            }
+        }
+        finish_group(&agents, current);
+        specific.or(wildcard).unwrap_or_default()
+    }
+
+    /// The longest matching rule wins, and Allow wins a tie
+    pub fn is_allowed(&self, path: &str) -> bool {
+        let mut best: Option<(usize, bool)> = None;
+        for (allow, pattern) in &self.rules {
+            if !pattern_matches(pattern, path) {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((len, best_allow)) => {
+                    pattern.len() > len || (pattern.len() == len && *allow && !best_allow)
+                }
+            };
+            if better {
+                best = Some((pattern.len(), *allow));
+            }
+        }
+        best.map(|(_, allow)| allow).unwrap_or(true)
+    }
+
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay
+    }
+}
+
+// Supports the `*` wildcard and the `$` end anchor
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(stripped) => (stripped, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let is_last = i == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_robots() {
+        let text = r#"
+# comment
+User-agent: *
+Disallow: /private/
+Crawl-delay: 2
+
+User-agent: guten-rs
+User-agent: other
+Disallow: /cache/
+Allow: /cache/public/
+Disallow: /*.zip$
+Crawl-delay: 0.5
+"#;
+        let robots = Robots::parse(text, "guten-rs/0.1.3 (+me@example.com)");
+        assert_eq!(robots.crawl_delay(), Some(Duration::from_millis(500)));
+        assert!(robots.is_allowed("/private/1.txt"));
+        assert!(!robots.is_allowed("/cache/1/"));
+        assert!(robots.is_allowed("/cache/public/1/"));
+        assert!(!robots.is_allowed("/1/2/1.zip"));
+        assert!(robots.is_allowed("/1/2/1.zip.txt"));
+
+        let robots = Robots::parse(text, "somebot/1.0");
+        assert_eq!(robots.crawl_delay(), Some(Duration::from_secs(2)));
+        assert!(!robots.is_allowed("/private/1.txt"));
+        assert!(robots.is_allowed("/cache/1/"));
+
+        let robots = Robots::parse("User-agent: *\nDisallow:\n", "guten-rs");
+        assert!(robots.is_allowed("/anything"));
+        assert!(Robots::allow_all().is_allowed("/anything"));
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Exit status of a stage stopped by Ctrl-C, 128 + SIGINT like a shell
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Returned by a stage that stopped early because of a [`Shutdown`]
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// A shutdown signal shared by every part of a stage. Cloning is cheap
+/// and all clones see the same signal.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    triggered: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown::default()
+    }
+
+    /// Triggers on the first Ctrl-C. A second Ctrl-C exits right away.
+    pub fn install_ctrlc(&self) -> Result<(), ctrlc::Error> {
+        let shutdown = self.clone();
+        ctrlc::set_handler(move || {
+            if shutdown.is_triggered() {
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            eprintln!("Interrupted, finishing up. Press Ctrl-C again to exit immediately");
+            shutdown.trigger();
+        })
+    }
+
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the signal has been triggered
+    pub async fn wait(&self) {
+        let notified = self.notify.notified();
+        if self.is_triggered() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Whether an error came from a stage being interrupted
+pub fn is_interrupted(error: &anyhow::Error) -> bool {
+    error.is::<Interrupted>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let shutdown = Shutdown::new();
+        let waiter = shutdown.clone();
+        let handle = tokio::spawn(async move { waiter.wait().await });
+        assert!(!shutdown.is_triggered());
+        shutdown.trigger();
+        handle.await.unwrap();
+        assert!(shutdown.is_triggered());
+        // already triggered, returns right away
+        shutdown.wait().await;
+        assert!(is_interrupted(&anyhow::Error::new(Interrupted)));
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Nothing listens on port 1, every request to it fails to connect
+pub const UNREACHABLE_URL: &str = "http://127.0.0.1:1/";
+
+/// Serves each path's bodies in turn and repeats the last one. A path
+/// without bodies never gets an answer, any other path is a 404.
+/// Returns the base url and the paths that were asked for.
+pub async fn serve(
+    bodies: HashMap<&'static str, Vec<Vec<u8>>>,
+) -> (String, Arc<Mutex<Vec<String>>>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let bodies = Arc::new(Mutex::new(bodies));
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let (bodies, seen) = (bodies.clone(), seen.clone());
+            tokio::spawn(async move {
+                let mut buf = vec![0; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+                seen.lock().unwrap().push(path.clone());
+                let response = match bodies.lock().unwrap().get_mut(path.as_str()) {
+                    Some(queue) if queue.len() > 1 => Some(("200 OK", queue.remove(0))),
+                    Some(queue) => queue.first().map(|body| ("200 OK", body.clone())),
+                    None => Some(("404 Not Found", Vec::new())),
+                };
+                let Some((status, body)) = response else {
+                    return std::future::pending().await;
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(&body).await;
+            });
+        }
+    });
+    (url, requests)
+}